@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+const HEADER: &str = "include/quicnet.h";
+
+/// Generate the C header of the staticlib,
+/// it is only rewritten when the C API changes.
+fn main() {
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    // the C API also exposes types outside of src/ffi, e.g. the error codes
+    println!("cargo:rerun-if-changed=src");
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("failed to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("failed to generate C header")
+        .write_to_file(crate_dir.join(HEADER));
+}
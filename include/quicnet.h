@@ -0,0 +1,435 @@
+#ifndef QUICNET_H
+#define QUICNET_H
+
+/* Generated by cbindgen from src/ffi, do not edit manually. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Congestion controller of a connection.
+typedef enum QuicnetCongestionControl {
+  QUICNET_CONGESTION_CONTROL_CUBIC = 0,
+  QUICNET_CONGESTION_CONTROL_NEW_RENO = 1,
+  QUICNET_CONGESTION_CONTROL_BBR = 2,
+} QuicnetCongestionControl;
+
+// Kind of a `QuicnetEvent`.
+typedef enum QuicnetEventKind {
+  QUICNET_EVENT_KIND_MESSAGE = 0,
+  QUICNET_EVENT_KIND_DATAGRAM = 1,
+  QUICNET_EVENT_KIND_PEER_CONNECTED = 2,
+  QUICNET_EVENT_KIND_PEER_DISCONNECTED = 3,
+  QUICNET_EVENT_KIND_ERROR = 4,
+} QuicnetEventKind;
+
+// Status codes returned by the C API.
+typedef enum QuicnetStatus {
+  QUICNET_STATUS_OK = 0,
+  // A pointer argument is null, or a string argument is invalid.
+  QUICNET_STATUS_INVALID_ARGUMENT = 1,
+  // Failed to start the server, for reasons other than the codes below.
+  QUICNET_STATUS_INIT_FAILED = 2,
+  // The server has already stopped.
+  QUICNET_STATUS_STOPPED = 3,
+  // Failed to connect to a peer.
+  QUICNET_STATUS_CONNECT_FAILED = 4,
+  // No connection to the peer.
+  QUICNET_STATUS_UNKNOWN_PEER = 5,
+  // Failed to send to a peer.
+  QUICNET_STATUS_SEND_FAILED = 6,
+  // The payload exceeds the maximum size.
+  QUICNET_STATUS_TOO_LARGE = 7,
+  // A panic was caught at the FFI boundary.
+  QUICNET_STATUS_PANIC = 8,
+  // No event is pending.
+  QUICNET_STATUS_NO_EVENT = 9,
+  // The config file could not be read or parsed.
+  QUICNET_STATUS_CONFIG_PARSE = 10,
+  // A certificate file could not be read or parsed.
+  QUICNET_STATUS_CERTIFICATE_LOAD = 11,
+  // The private key file could not be read or parsed.
+  QUICNET_STATUS_KEY_LOAD = 12,
+  // The TLS configuration is invalid, e.g. an unparsable CA certificate or private key.
+  QUICNET_STATUS_TLS_BUILD = 13,
+  // The UDP socket could not be bound.
+  QUICNET_STATUS_BIND = 14,
+  // The certificate of a peer was rejected.
+  QUICNET_STATUS_PEER_VERIFICATION = 15,
+  // The runtime or one of its threads could not be started.
+  QUICNET_STATUS_RUNTIME = 16,
+  // A config value is out of range.
+  QUICNET_STATUS_INVALID_CONFIG = 17,
+  // A certificate revocation list could not be read, parsed or verified.
+  QUICNET_STATUS_CRL_LOAD = 18,
+} QuicnetStatus;
+
+// Opaque handle to a running server.
+typedef struct QuicnetServer QuicnetServer;
+
+// Releases the buffer owned by `owner`.
+typedef void (*QuicnetReleaseFn)(void *owner);
+
+// A byte buffer passed across the FFI boundary without copying.
+//
+// `data` stays valid until `release` is called with `owner`, which must
+// happen exactly once, e.g. through `quicnet_buffer_release`.
+// `release` is null if there is nothing to release.
+typedef struct QuicnetBuffer {
+  const uint8_t *data;
+  size_t len;
+  QuicnetReleaseFn release;
+  void *owner;
+} QuicnetBuffer;
+
+// An event taken from the queue.
+//
+// `peer` and `message` stay valid until the next `quicnet_poll_event` or
+// `quicnet_next_event` call on the same server, or until it is freed.
+// The caller owns `payload` and must release it.
+typedef struct QuicnetEvent {
+  enum QuicnetEventKind kind;
+  // Nul-terminated peer name, null for errors of unidentified peers.
+  const char *peer;
+  // Payload of messages and datagrams, empty otherwise.
+  struct QuicnetBuffer payload;
+  // `QuicnetStatus` of errors, 0 otherwise.
+  int status;
+  // Nul-terminated message of errors, null otherwise.
+  const char *message;
+} QuicnetEvent;
+
+// Called with a message or datagram from `peer`. `peer` is only valid
+// during the call, while the callee owns `payload` and must release it.
+typedef void (*QuicnetMessageCallback)(void *user_data,
+                                       const char *peer,
+                                       struct QuicnetBuffer payload);
+
+// Called when `peer` connects or disconnects.
+// `peer` is only valid during the call.
+typedef void (*QuicnetPeerCallback)(void *user_data, const char *peer);
+
+// Called on a failure not reported to any caller. `peer` is null when
+// the peer is not identified yet. Both strings are only valid during the call.
+typedef void (*QuicnetErrorCallback)(void *user_data,
+                                     const char *peer,
+                                     int status,
+                                     const char *message);
+
+// Callbacks receiving server events, any of them may be null.
+//
+// By default callbacks are invoked from the server runtime threads,
+// possibly concurrently, so they and `user_data` must be thread-safe.
+// With `serialized` set, they are invoked one at a time, in order,
+// from a single dispatcher thread owned by the server.
+//
+// Callbacks must not block for long, and must not call
+// `quicnet_server_free` on their own server. Blocking API calls
+// are only allowed from the dispatcher thread.
+// No callback is invoked after `quicnet_server_free` returns.
+typedef struct QuicnetCallbacks {
+  void *user_data;
+  QuicnetMessageCallback on_message;
+  QuicnetMessageCallback on_datagram;
+  QuicnetPeerCallback on_peer_connected;
+  QuicnetPeerCallback on_peer_disconnected;
+  QuicnetErrorCallback on_error;
+  bool serialized;
+} QuicnetCallbacks;
+
+// Called once with the `QuicnetStatus` of an asynchronous send.
+typedef void (*QuicnetSendCallback)(void *user_data, int status);
+
+// Snapshot of the connection to a peer.
+typedef struct QuicnetPeerStats {
+  enum QuicnetCongestionControl congestion_control;
+  // Current round-trip time estimate in microseconds.
+  uint64_t rtt_us;
+  // Current congestion window in bytes.
+  uint64_t cwnd;
+  uint64_t congestion_events;
+  // QUIC packets sent.
+  uint64_t sent_packets;
+  uint64_t lost_packets;
+  uint64_t lost_bytes;
+  // UDP datagrams and bytes sent and received, including QUIC overhead.
+  uint64_t sent_datagrams;
+  uint64_t sent_bytes;
+  uint64_t received_datagrams;
+  uint64_t received_bytes;
+  uint64_t black_holes_detected;
+  // Proxy of the path MTU: the largest datagram payload fitting it,
+  // the MTU less the QUIC packet overhead.
+  // 0 if datagrams are not supported by both sides.
+  size_t path_mtu_proxy;
+} QuicnetPeerStats;
+
+// Aggregate over all connected peers.
+typedef struct QuicnetEndpointStats {
+  size_t peers;
+  // Largest round-trip time of any peer in microseconds.
+  uint64_t max_rtt_us;
+  uint64_t congestion_events;
+  uint64_t sent_packets;
+  uint64_t lost_packets;
+  uint64_t lost_bytes;
+  uint64_t sent_datagrams;
+  uint64_t sent_bytes;
+  uint64_t received_datagrams;
+  uint64_t received_bytes;
+} QuicnetEndpointStats;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Release a buffer received from quicnet, and reset it to an empty buffer.
+//
+// # Safety
+//
+// `buffer` must be null or a valid buffer not released before.
+void quicnet_buffer_release(struct QuicnetBuffer *buffer);
+
+// Take the next queued event without blocking.
+//
+// Returns `QUICNET_STATUS_NO_EVENT` if none is pending, or
+// `QUICNET_STATUS_STOPPED` once the server has stopped and all events are taken.
+//
+// # Safety
+//
+// `server` must be a live handle from `quicnet_server_init_with_queue`,
+// `out` must be writable. Events must be taken by one thread at a time.
+int quicnet_poll_event(const struct QuicnetServer *server, struct QuicnetEvent *out);
+
+// Take the next queued event, waiting at most `timeout_ms` for one
+// (forever if negative). Returns like `quicnet_poll_event`.
+//
+// # Safety
+//
+// `server` must be a live handle from `quicnet_server_init_with_queue`,
+// `out` must be writable. Events must be taken by one thread at a time.
+int quicnet_next_event(const struct QuicnetServer *server,
+                       int64_t timeout_ms,
+                       struct QuicnetEvent *out);
+
+// The eventfd of the queue, readable while events are pending or once
+// the server has stopped. Returns -1 without a queue, or on platforms
+// without eventfd.
+//
+// The fd is owned by the server and closed by `quicnet_server_free`.
+// It must only be polled for readability, not read.
+//
+// # Safety
+//
+// `server` must be null or a live handle.
+int quicnet_server_event_fd(const struct QuicnetServer *server);
+
+// Number of events dropped because the queue was full, 0 without a queue.
+//
+// # Safety
+//
+// `server` must be null or a live handle.
+uint64_t quicnet_server_dropped_events(const struct QuicnetServer *server);
+
+// Load the config file at `config_path` and start a server with `n_threads`
+// runtime threads (0 for one per CPU). The handle is written to `out`.
+//
+// Events are delivered to `callbacks`, which is copied and may be null
+// to ignore all events.
+//
+// # Safety
+//
+// `config_path` must be a nul-terminated string, `out` must be writable,
+// `callbacks` must be null or valid, and its `user_data` must outlive the server.
+// The handle must be released with `quicnet_server_free`.
+int quicnet_server_init_from_file(const char *config_path,
+                                  size_t n_threads,
+                                  const struct QuicnetCallbacks *callbacks,
+                                  struct QuicnetServer **out);
+
+// Like `quicnet_server_init_from_file`, but events are queued for
+// `quicnet_poll_event` and `quicnet_next_event` instead of invoking callbacks.
+//
+// Once `queue_capacity` events are queued, further messages, datagrams and
+// errors are dropped and counted by `quicnet_server_dropped_events`.
+// Peer connection events are never dropped.
+//
+// # Safety
+//
+// `config_path` must be a nul-terminated string, `out` must be writable.
+// The handle must be released with `quicnet_server_free`.
+int quicnet_server_init_with_queue(const char *config_path,
+                                   size_t n_threads,
+                                   size_t queue_capacity,
+                                   struct QuicnetServer **out);
+
+// Connect to the peer `domain` at `addr` (e.g. `"127.0.0.1:12345"`),
+// blocking until the connection is established.
+// The peer is registered under the smallest name of its certificate,
+// as reported by `on_peer_connected`, which may differ from `domain`.
+//
+// # Safety
+//
+// `server` must be a live handle, `addr` and `domain` nul-terminated strings.
+// Must not be called from a server runtime thread.
+int quicnet_server_connect(const struct QuicnetServer *server,
+                           const char *addr,
+                           const char *domain);
+
+// Re-read the certificates and private key for new connections, keeping
+// existing connections. Nothing is changed if the files are invalid.
+//
+// # Safety
+//
+// `server` must be a live handle. Must not be called from a server runtime thread.
+int quicnet_server_reload_tls(const struct QuicnetServer *server);
+
+// Send `len` bytes at `data` to `peer` as a message,
+// blocking until the message is written. The data is copied.
+//
+// # Safety
+//
+// `server` must be a live handle, `peer` a nul-terminated string,
+// `data` must point to `len` readable bytes. Must not be called from a server runtime thread.
+int quicnet_server_send(const struct QuicnetServer *server,
+                        const char *peer,
+                        const uint8_t *data,
+                        size_t len);
+
+// Send `payload` to `peer` as a message without copying it, and return
+// without waiting for the message to be written.
+//
+// `payload` is always released, once quicnet no longer references it.
+// If `QUICNET_STATUS_OK` is returned, `on_complete` (if not null) is called
+// exactly once with the result, from a server runtime thread or possibly
+// before this function returns if the server is stopping.
+//
+// # Safety
+//
+// `server` must be a live handle, `peer` a nul-terminated string. `payload` must
+// stay valid and unmodified until released, and its `release` and `on_complete`
+// must be callable from any thread.
+int quicnet_server_send_buffer(const struct QuicnetServer *server,
+                               const char *peer,
+                               struct QuicnetBuffer payload,
+                               QuicnetSendCallback on_complete,
+                               void *user_data);
+
+// Send `len` bytes at `data` to `peer` as an unreliable datagram. The data is copied.
+//
+// # Safety
+//
+// `server` must be a live handle, `peer` a nul-terminated string,
+// `data` must point to `len` readable bytes. Must not be called from a server runtime thread.
+int quicnet_server_send_datagram(const struct QuicnetServer *server,
+                                 const char *peer,
+                                 const uint8_t *data,
+                                 size_t len);
+
+// Number of connected peers, 0 if `server` is null.
+//
+// # Safety
+//
+// `server` must be null or a live handle.
+size_t quicnet_server_peer_count(const struct QuicnetServer *server);
+
+// Stop accepting connections, wait at most `drain_timeout_ms` for in-flight
+// streams and sent messages to be read, close all connections, and block
+// until the server thread exits.
+//
+// # Safety
+//
+// `server` must be a live handle. Must not be called from a server runtime thread.
+int quicnet_server_shutdown(const struct QuicnetServer *server, uint64_t drain_timeout_ms);
+
+// Close all connections immediately, and block until the server thread exits.
+//
+// # Safety
+//
+// `server` must be a live handle. Must not be called from a server runtime thread.
+int quicnet_server_abort(const struct QuicnetServer *server);
+
+// Release a server handle, shutting the server down if still running,
+// and waiting for serialized callbacks of queued events to return.
+//
+// For a server with an event queue, events not taken yet are discarded,
+// the strings of the last taken event are released and the eventfd is
+// closed. Payloads already taken remain owned by the caller.
+//
+// # Safety
+//
+// `server` must be null or a handle from `quicnet_server_init_from_file`
+// or `quicnet_server_init_with_queue` that is not used afterwards.
+// Must not be called from a callback, a server runtime thread, or while
+// another thread is waiting in `quicnet_next_event`.
+void quicnet_server_free(struct QuicnetServer *server);
+
+// Write a snapshot of the connection to `peer` to `out`.
+// Returns `QUICNET_STATUS_UNKNOWN_PEER` if not connected.
+//
+// # Safety
+//
+// `server` must be a live handle, `peer` a nul-terminated string,
+// `out` must be writable.
+int quicnet_server_peer_stats(const struct QuicnetServer *server,
+                              const char *peer,
+                              struct QuicnetPeerStats *out);
+
+// Write the aggregate over all connected peers to `out`.
+//
+// # Safety
+//
+// `server` must be a live handle, `out` must be writable.
+int quicnet_server_endpoint_stats(const struct QuicnetServer *server,
+                                  struct QuicnetEndpointStats *out);
+
+// Write the metrics in the Prometheus text exposition format to `out`,
+// as a buffer that is not nul-terminated. The caller must release it.
+//
+// # Safety
+//
+// `server` must be a live handle, `out` must be writable.
+int quicnet_server_metrics(const struct QuicnetServer *server, struct QuicnetBuffer *out);
+
+// Permit the domain pattern `domain`, e.g. `*.cluster.example`,
+// for new connections in both directions.
+// No effect while the whitelist is disabled.
+//
+// # Safety
+//
+// `server` must be a live handle, `domain` a nul-terminated string.
+// Must not be called from a server runtime thread.
+int quicnet_server_whitelist_add(const struct QuicnetServer *server, const char *domain);
+
+// Remove the domain pattern `domain`, rejecting peers it permitted for new
+// connections in both directions. With `close_revoked`, existing connections
+// to those peers, in either direction, are closed.
+// No effect while the whitelist is disabled.
+//
+// # Safety
+//
+// `server` must be a live handle, `domain` a nul-terminated string.
+// Must not be called from a server runtime thread.
+int quicnet_server_whitelist_remove(const struct QuicnetServer *server,
+                                    const char *domain,
+                                    bool close_revoked);
+
+// Replace the whitelist with the `n_domains` patterns at `domains`, for new
+// connections in both directions. With `close_revoked`, existing connections
+// to peers no longer permitted, in either direction, are closed.
+// An empty list rejects all peers.
+//
+// # Safety
+//
+// `server` must be a live handle, `domains` null or pointing to `n_domains`
+// nul-terminated strings. Must not be called from a server runtime thread.
+int quicnet_server_whitelist_replace(const struct QuicnetServer *server,
+                                     const char *const *domains,
+                                     size_t n_domains,
+                                     bool close_revoked);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* QUICNET_H */
@@ -1,13 +1,20 @@
+use super::{
+    crl::RevocationList,
+    pins::{check_validity, Pinned, Pins},
+    whitelist::{matched_name, Whitelist},
+};
+use crate::{metrics::Metrics, server::Direction};
 use rustls::{
     server::{ClientCertVerified, ClientCertVerifier},
     Certificate, CertificateError, DistinguishedName,
 };
 use std::{sync::Arc, time::SystemTime};
-use webpki::{DnsName, TlsClientTrustAnchors, TrustAnchor};
+use webpki::{TlsClientTrustAnchors, TrustAnchor};
 
 type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 
-static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
+/// Also used to verify server certificates, see `AuthenticatedServer`.
+pub(super) static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
     &webpki::ECDSA_P256_SHA256,
     &webpki::ECDSA_P256_SHA384,
     &webpki::ECDSA_P384_SHA256,
@@ -23,17 +30,27 @@ static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
 ];
 
 /// A `ClientCertVerifier` that will ensure that every client provides a trusted
-/// certificate, check the the certificate is within a white list of domain names.
+/// certificate, check the the certificate is within a white list of domain names,
+/// that it carries the pinned key of pinned peers, and that no certificate
+/// of its chain is revoked.
+///
+/// Certificates of pin-only peers are trusted on their pin, instead of `roots`.
 pub(crate) struct AllowWhitelistAuthenticatedClient {
     roots: Vec<Certificate>,
     subjects: Vec<DistinguishedName>,
-    whitelist: Option<Vec<webpki::DnsName>>,
+    whitelist: Arc<Whitelist>,
+    pins: Arc<Pins>,
+    crl: Arc<RevocationList>,
+    metrics: Arc<Metrics>,
 }
 
 impl AllowWhitelistAuthenticatedClient {
     pub fn new(
         roots: Vec<Certificate>,
-        whitelist: Option<Vec<DnsName>>,
+        whitelist: Arc<Whitelist>,
+        pins: Arc<Pins>,
+        crl: Arc<RevocationList>,
+        metrics: Arc<Metrics>,
     ) -> Result<Self, rustls::Error> {
         Ok(Self {
             subjects: trust_roots(&roots)?
@@ -42,6 +59,9 @@ impl AllowWhitelistAuthenticatedClient {
                 .collect(),
             roots,
             whitelist,
+            pins,
+            crl,
+            metrics,
         })
     }
 
@@ -66,32 +86,80 @@ impl ClientCertVerifier for AllowWhitelistAuthenticatedClient {
         intermediates: &[Certificate],
         now: SystemTime,
     ) -> Result<ClientCertVerified, rustls::Error> {
-        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref()).map_err(pki_error)?;
-        let chain = intermediate_chain(intermediates);
-        let trust_roots = trust_roots(&self.roots)?;
-        let now = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+        let rejected = |error: webpki::Error| {
+            self.metrics.client_cert_rejected(rejection_reason(&error));
+            pki_error(error)
+        };
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref()).map_err(rejected)?;
+        // resolved first, pins also apply to the name matched by the whitelist
+        let whitelist = self.whitelist.load();
+        let matched = match whitelist.as_ref() {
+            Some(whitelist) => Some(matched_name(whitelist, end_entity).map_err(rejected)?),
+            None => None,
+        };
+        let pinned = self
+            .pins
+            .check(end_entity, matched.as_ref().and_then(Option::as_ref))
+            .map_err(|e| {
+                tracing::warn!("rejected client certificate: {e}");
+                self.metrics.client_cert_rejected("pin_mismatch");
+                rustls::Error::from(e)
+            })?;
+
+        if pinned == Pinned::PinOnly {
+            check_validity(end_entity, now).map_err(rejected)?;
+        } else {
+            let chain = intermediate_chain(intermediates);
+            let trust_roots = trust_roots(&self.roots)?;
+            let now =
+                webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
 
-        let trusted_anchors = TlsClientTrustAnchors(&trust_roots);
+            let trusted_anchors = TlsClientTrustAnchors(&trust_roots);
 
-        cert.verify_is_valid_tls_client_cert(SUPPORTED_SIG_ALGS, &trusted_anchors, &chain, now)
-            .map_err(pki_error)
-            .map(|_| ClientCertVerified::assertion())?;
+            cert.verify_is_valid_tls_client_cert(SUPPORTED_SIG_ALGS, &trusted_anchors, &chain, now)
+                .map_err(rejected)?;
+        }
 
-        if let Some(whitelist) = &self.whitelist {
-            cert.verify_is_valid_for_at_least_one_dns_name(whitelist.iter().map(|o| o.as_ref()))
-                .map_err(pki_error)
-                .map(|_| ClientCertVerified::assertion())
-        } else {
-            Ok(ClientCertVerified::assertion())
+        if self
+            .crl
+            .is_revoked(end_entity, intermediates)
+            .map_err(|_| rejected(webpki::Error::BadDer))?
+        {
+            tracing::warn!("rejected revoked client certificate");
+            self.metrics.revoked_certificate(Direction::Inbound);
+            self.metrics.client_cert_rejected("revoked");
+            return Err(CertificateError::Revoked.into());
         }
+
+        match matched {
+            Some(None) => Err(rejected(webpki::Error::CertNotValidForName)),
+            _ => Ok(ClientCertVerified::assertion()),
+        }
+    }
+}
+
+/// Metrics label of a rejected client certificate.
+fn rejection_reason(error: &webpki::Error) -> &'static str {
+    use webpki::Error::*;
+    match error {
+        UnknownIssuer => "unknown_issuer",
+        // only checked against the whitelist
+        CertNotValidForName => "not_whitelisted",
+        CertExpired => "expired",
+        CertNotValidYet => "not_yet_valid",
+        BadDer | BadDerTime => "bad_encoding",
+        InvalidSignatureForPublicKey
+        | UnsupportedSignatureAlgorithm
+        | UnsupportedSignatureAlgorithmForPublicKey => "bad_signature",
+        _ => "other",
     }
 }
 
-fn intermediate_chain(intermediates: &[Certificate]) -> Vec<&[u8]> {
+pub(super) fn intermediate_chain(intermediates: &[Certificate]) -> Vec<&[u8]> {
     intermediates.iter().map(|cert| cert.0.as_ref()).collect()
 }
 
-fn trust_roots(roots: &[Certificate]) -> Result<Vec<TrustAnchor>, rustls::Error> {
+pub(super) fn trust_roots(roots: &[Certificate]) -> Result<Vec<TrustAnchor<'_>>, rustls::Error> {
     let mut anchors = Vec::with_capacity(roots.len());
     for root in roots {
         let anchor = TrustAnchor::try_from_cert_der(&root.0).map_err(pki_error)?;
@@ -100,7 +168,7 @@ fn trust_roots(roots: &[Certificate]) -> Result<Vec<TrustAnchor>, rustls::Error>
     Ok(anchors)
 }
 
-fn pki_error(error: webpki::Error) -> rustls::Error {
+pub(super) fn pki_error(error: webpki::Error) -> rustls::Error {
     use webpki::Error::*;
     match error {
         BadDer | BadDerTime => CertificateError::BadEncoding.into(),
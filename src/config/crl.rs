@@ -0,0 +1,238 @@
+use crate::error::QuicnetError;
+use rustls_pemfile::Item;
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+use x509_parser::{
+    certificate::X509Certificate, error::X509Error, revocation_list::CertificateRevocationList,
+};
+
+/// Serial numbers of revoked certificates, by issuer.
+///
+/// Only CRLs issued and signed by one of the CA certificates are accepted.
+/// Every certificate of a presented chain is checked against them.
+#[derive(Default)]
+pub(crate) struct RevocationList {
+    /// Raw DER of the issuer name and raw serial number.
+    revoked: HashSet<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RevocationList {
+    /// Load the CRLs in `path`, a PEM or DER file, or a directory of them.
+    ///
+    /// Fails if a CRL is not signed by one of `ca`, or if the file, or every
+    /// file of the directory, contains no CRL. Other files of a directory,
+    /// such as a README, are skipped with a warning.
+    pub(crate) fn load(path: &Path, ca: &[rustls::Certificate]) -> Result<Self, QuicnetError> {
+        let ca = ca
+            .iter()
+            .map(|cert| parse_certificate(&cert.0))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| crl_error(path, format!("failed to parse CA: {e}")))?;
+        let directory = path.is_dir();
+        let mut list = RevocationList::default();
+        let mut loaded = 0;
+        for file in crl_files(path)? {
+            let ders = read_crls(&file)?;
+            let crls = match parse_crls(&ders) {
+                Ok(crls) => crls,
+                Err(reason) if directory => {
+                    tracing::warn!("skipping {}: {reason}", file.display());
+                    continue;
+                }
+                Err(reason) => return Err(crl_error(&file, reason)),
+            };
+            for crl in &crls {
+                list.add(crl, &ca)
+                    .map_err(|reason| crl_error(&file, reason))?;
+            }
+            loaded += crls.len();
+        }
+        if loaded == 0 {
+            return Err(crl_error(path, "no CRL found in directory"));
+        }
+        Ok(list)
+    }
+
+    fn add(
+        &mut self,
+        crl: &CertificateRevocationList,
+        ca: &[X509Certificate],
+    ) -> Result<(), String> {
+        let issuer = ca
+            .iter()
+            .find(|cert| cert.subject().as_raw() == crl.issuer().as_raw())
+            .ok_or_else(|| format!("CRL issuer {} is not a CA certificate", crl.issuer()))?;
+        crl.verify_signature(issuer.public_key())
+            .map_err(|e| format!("invalid CRL signature: {e}"))?;
+        warn_outdated(crl);
+        let issuer = crl.issuer().as_raw().to_vec();
+        self.revoked.extend(
+            crl.iter_revoked_certificates()
+                .map(|revoked| (issuer.clone(), revoked.raw_serial().to_vec())),
+        );
+        Ok(())
+    }
+
+    /// Whether `end_entity` or one of `intermediates` is listed as revoked by its issuer.
+    pub(crate) fn is_revoked(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+    ) -> Result<bool, X509Error> {
+        if self.revoked.is_empty() {
+            return Ok(false);
+        }
+        for cert in std::iter::once(end_entity).chain(intermediates) {
+            let cert = parse_certificate(&cert.0)?;
+            let key = (cert.issuer().as_raw().to_vec(), cert.raw_serial().to_vec());
+            if self.revoked.contains(&key) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Files of the CRL `path`, sorted if it is a directory.
+///
+/// Also used to watch the CRLs for modifications.
+pub(crate) fn crl_files(path: &Path) -> Result<Vec<PathBuf>, QuicnetError> {
+    let error = |e: std::io::Error| crl_error(path, e.to_string());
+    if !path.metadata().map_err(error)?.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path).map_err(error)? {
+        let entry = entry.map_err(error)?;
+        if entry.file_type().map_err(error)?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The DER of every CRL in a PEM file, or the content of a DER file.
+fn read_crls(path: &Path) -> Result<Vec<Vec<u8>>, QuicnetError> {
+    let error = |e: std::io::Error| crl_error(path, e.to_string());
+    let mut content = Vec::new();
+    BufReader::new(File::open(path).map_err(error)?)
+        .read_to_end(&mut content)
+        .map_err(error)?;
+    // DER starts with a SEQUENCE tag
+    if content.first() == Some(&0x30) {
+        return Ok(vec![content]);
+    }
+    Ok(rustls_pemfile::read_all(&mut content.as_slice())
+        .map_err(error)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::Crl(der) => Some(der),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Parse every CRL in `ders`, failing if there is none.
+fn parse_crls(ders: &[Vec<u8>]) -> Result<Vec<CertificateRevocationList<'_>>, String> {
+    if ders.is_empty() {
+        return Err("no CRL found in file".to_string());
+    }
+    ders.iter()
+        .map(|der| {
+            x509_parser::parse_x509_crl(der)
+                .map(|(_, crl)| crl)
+                .map_err(|e| format!("invalid CRL: {e}"))
+        })
+        .collect()
+}
+
+fn parse_certificate(der: &[u8]) -> Result<X509Certificate<'_>, X509Error> {
+    x509_parser::parse_x509_certificate(der)
+        .map(|(_, cert)| cert)
+        .map_err(|e| match e {
+            x509_parser::nom::Err::Error(e) | x509_parser::nom::Err::Failure(e) => e,
+            x509_parser::nom::Err::Incomplete(_) => X509Error::InvalidCertificate,
+        })
+}
+
+/// An outdated CRL is still used, revocations are not undone by expiry.
+fn warn_outdated(crl: &CertificateRevocationList) {
+    if let Some(next_update) = crl.next_update() {
+        if next_update.timestamp() < x509_parser::time::ASN1Time::now().timestamp() {
+            tracing::warn!("CRL of {} is outdated since {next_update}", crl.issuer());
+        }
+    }
+}
+
+fn crl_error(path: &Path, reason: impl Into<String>) -> QuicnetError {
+    QuicnetError::CrlLoad {
+        path: path.to_owned(),
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod crl_tests {
+    use super::*;
+    use crate::config::tls::load_certificates;
+
+    const CA_PATH: &str = "./certs/RootCA.pem";
+    const CRL_PEM: &str = "./certs/crl.pem";
+    const CRL_DER: &str = "./certs/crl.der";
+    /// `crl.pem` and a README.
+    const CRL_DIR: &str = "./certs/crl.d";
+    const REVOKED_CRT: &str = "./certs/zqxbnvtk.uk/zqxbnvtk.uk.crt";
+    const TEST_CRT: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.crt";
+
+    #[test]
+    fn test_is_revoked() {
+        let ca = load_certificates(CA_PATH, None).expect("failed to load ca");
+        let revoked = load_certificates(REVOKED_CRT, None).expect("failed to load certs");
+        let valid = load_certificates(TEST_CRT, None).expect("failed to load certs");
+        for path in [CRL_PEM, CRL_DER] {
+            let list = RevocationList::load(Path::new(path), &ca).expect("failed to load CRL");
+            assert!(list.is_revoked(&revoked[0], &[]).unwrap(), "{path}");
+            assert!(!list.is_revoked(&valid[0], &[]).unwrap(), "{path}");
+            // revoked certificates are also rejected as intermediates
+            assert!(list.is_revoked(&valid[0], &revoked).unwrap(), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_directory() {
+        let ca = load_certificates(CA_PATH, None).expect("failed to load ca");
+        let revoked = load_certificates(REVOKED_CRT, None).expect("failed to load certs");
+        // the README is skipped
+        let list = RevocationList::load(Path::new(CRL_DIR), &ca).expect("failed to load CRLs");
+        assert!(list.is_revoked(&revoked[0], &[]).unwrap());
+    }
+
+    #[test]
+    fn test_untrusted_issuer() {
+        // the CRL is not issued by a leaf certificate
+        let ca = load_certificates(TEST_CRT, None).expect("failed to load certs");
+        assert!(matches!(
+            RevocationList::load(Path::new(CRL_PEM), &ca),
+            Err(QuicnetError::CrlLoad { .. })
+        ));
+    }
+
+    #[test]
+    fn test_no_crl() {
+        let ca = load_certificates(CA_PATH, None).expect("failed to load ca");
+        for path in [CA_PATH, "./certs/empty", "./certs/missing.pem"] {
+            assert!(
+                matches!(
+                    RevocationList::load(Path::new(path), &ca),
+                    Err(QuicnetError::CrlLoad { .. })
+                ),
+                "{path}"
+            );
+        }
+    }
+}
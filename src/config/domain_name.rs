@@ -1,34 +1,169 @@
 use serde::{de::Visitor, Deserialize, Deserializer};
+use std::{fmt::Display, str::FromStr};
 use webpki::DnsName;
 
-#[derive(Clone)]
-pub struct DomainName(pub(crate) webpki::DnsName);
+/// A whitelist entry, matched case-insensitively against the DNS names
+/// in the subject alternative names of a certificate.
+///
+/// - `node.example` matches exactly `node.example`.
+/// - `*.cluster.example` matches names with exactly one more label,
+///   e.g. `a.cluster.example`, but not `cluster.example` or `a.b.cluster.example`.
+/// - `.example` matches names with one or more labels before `example`,
+///   e.g. `a.example` and `a.b.example`, but not `example` itself.
+///
+/// Wildcard names in certificates only match exact entries, through webpki.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DomainPattern {
+    Exact(DnsName),
+    /// The parent domain of `*.parent`.
+    Wildcard(DnsName),
+    /// The domain of `.domain`.
+    Suffix(DnsName),
+}
+
+impl DomainPattern {
+    /// Whether the certificate name `name` matches this pattern.
+    pub fn matches(&self, name: &DnsName) -> bool {
+        let name: &str = AsRef::<str>::as_ref(name);
+        match self {
+            DomainPattern::Exact(domain) => name.eq_ignore_ascii_case(AsRef::<str>::as_ref(domain)),
+            DomainPattern::Wildcard(parent) => match name.split_once('.') {
+                Some((label, rest)) => {
+                    !label.is_empty() && rest.eq_ignore_ascii_case(AsRef::<str>::as_ref(parent))
+                }
+                None => false,
+            },
+            DomainPattern::Suffix(domain) => {
+                let domain: &str = AsRef::<str>::as_ref(domain);
+                name.len() > domain.len() + 1
+                    && name.as_bytes()[name.len() - domain.len() - 1] == b'.'
+                    && name[name.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            }
+        }
+    }
+}
+
+/// Invalid whitelist entry.
+#[derive(Debug)]
+pub struct InvalidPattern(String);
+
+impl Display for InvalidPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid domain pattern {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPattern {}
+
+impl FromStr for DomainPattern {
+    type Err = InvalidPattern;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || InvalidPattern(s.to_string());
+        let parse = |name: &str| {
+            webpki::DnsNameRef::try_from_ascii_str(name)
+                .map(DnsName::from)
+                .map_err(|_| error())
+        };
+        // the remainder must not itself be a pattern, e.g. `*.*.example` or `..example`
+        if let Some(parent) = s.strip_prefix("*.") {
+            parse(parent).map(DomainPattern::Wildcard)
+        } else if let Some(domain) = s.strip_prefix('.') {
+            parse(domain).map(DomainPattern::Suffix)
+        } else {
+            parse(s).map(DomainPattern::Exact)
+        }
+    }
+}
 
-struct DomainNameVisitor;
+impl Display for DomainPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomainPattern::Exact(domain) => write!(f, "{}", AsRef::<str>::as_ref(domain)),
+            DomainPattern::Wildcard(parent) => write!(f, "*.{}", AsRef::<str>::as_ref(parent)),
+            DomainPattern::Suffix(domain) => write!(f, ".{}", AsRef::<str>::as_ref(domain)),
+        }
+    }
+}
 
-impl<'de> Visitor<'de> for DomainNameVisitor {
-    type Value = DomainName;
+struct DomainPatternVisitor;
+
+impl<'de> Visitor<'de> for DomainPatternVisitor {
+    type Value = DomainPattern;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("expecting a domain name")
+        formatter.write_str("a domain name, `*.parent` or `.suffix`")
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        let name_ref =
-            webpki::DnsNameRef::try_from_ascii_str(v).map_err(|e| serde::de::Error::custom(e))?;
-        let name = DnsName::from(name_ref);
-        Ok(DomainName(name))
+        v.parse().map_err(serde::de::Error::custom)
     }
 }
 
-impl<'de> Deserialize<'de> for DomainName {
+impl<'de> Deserialize<'de> for DomainPattern {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_string(DomainNameVisitor)
+        deserializer.deserialize_string(DomainPatternVisitor)
+    }
+}
+
+#[cfg(test)]
+mod domain_name_tests {
+    use super::*;
+
+    fn dns_name(name: &str) -> DnsName {
+        DnsName::from(webpki::DnsNameRef::try_from_ascii_str(name).unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            "node.example".parse(),
+            Ok(DomainPattern::Exact(_))
+        ));
+        assert!(matches!(
+            "*.cluster.example".parse(),
+            Ok(DomainPattern::Wildcard(_))
+        ));
+        assert!(matches!(".example".parse(), Ok(DomainPattern::Suffix(_))));
+        for invalid in [
+            "",
+            "*",
+            "*.",
+            ".",
+            "..example",
+            "*.*.example",
+            "a.*.example",
+            "*a.example",
+        ] {
+            assert!(invalid.parse::<DomainPattern>().is_err(), "{invalid}");
+        }
+        let pattern: DomainPattern = "*.Cluster.example".parse().unwrap();
+        assert_eq!(pattern.to_string(), "*.cluster.example");
+    }
+
+    #[test]
+    fn test_matches() {
+        let wildcard: DomainPattern = "*.cluster.example".parse().unwrap();
+        assert!(wildcard.matches(&dns_name("a.cluster.example")));
+        assert!(wildcard.matches(&dns_name("A.Cluster.Example")));
+        assert!(!wildcard.matches(&dns_name("cluster.example")));
+        assert!(!wildcard.matches(&dns_name("a.b.cluster.example")));
+        assert!(!wildcard.matches(&dns_name("acluster.example")));
+
+        let suffix: DomainPattern = ".example".parse().unwrap();
+        assert!(suffix.matches(&dns_name("a.example")));
+        assert!(suffix.matches(&dns_name("a.b.example")));
+        assert!(!suffix.matches(&dns_name("example")));
+        assert!(!suffix.matches(&dns_name("anexample")));
+
+        let exact: DomainPattern = "node.example".parse().unwrap();
+        assert!(exact.matches(&dns_name("NODE.example")));
+        assert!(!exact.matches(&dns_name("a.node.example")));
     }
 }
@@ -1,40 +1,99 @@
 pub mod client_auth;
+pub mod crl;
 pub mod domain_name;
+pub mod pins;
+pub mod pkcs12;
 pub mod quic;
+pub mod server_auth;
 pub mod tls;
+pub mod whitelist;
 
-use self::domain_name::DomainName;
+use self::{domain_name::DomainPattern, pins::PeerConfig, quic::TransportConfig};
+use crate::error::QuicnetError;
 use serde::Deserialize;
 use std::{net::SocketAddr, path::PathBuf};
 
+/// Default maximum size of a message frame, 16 MiB.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
 #[derive(Deserialize)]
 pub struct ServerConfig {
     pub ca: PathBuf,
     pub certs: PathBuf,
     pub key: PathBuf,
     pub addr: SocketAddr,
-    pub whitelist: Option<Vec<DomainName>>,
+    /// Permitted peers, inbound and outbound, see `DomainPattern`.
+    /// All trusted peers if unset.
+    pub whitelist: Option<Vec<DomainPattern>>,
+    /// Certificate revocation lists issued by `ca`, a PEM or DER file or a
+    /// directory of them. Peers are rejected if their certificate or an
+    /// intermediate of its chain is listed as revoked. Files of a directory
+    /// that are not CRLs are skipped.
+    pub crl: Option<PathBuf>,
+    /// Settings of individual peers, e.g. their pinned public keys.
+    pub peers: Option<Vec<PeerConfig>>,
+    /// Password of `ca`, read only if it is a PKCS#12 bundle.
+    pub ca_password: Option<PasswordConfig>,
+    /// Password of `certs`, read only if it is a PKCS#12 bundle.
+    pub certs_password: Option<PasswordConfig>,
+    /// Password of `key`, read only if it is a PKCS#12 bundle or a PKCS#8 encrypted key.
+    pub key_password: Option<PasswordConfig>,
+    /// Larger frames are rejected, defaults to `DEFAULT_MAX_FRAME_SIZE`.
+    pub max_frame_size: Option<usize>,
+    /// QUIC transport parameters.
+    pub transport: Option<TransportConfig>,
+    /// Check `ca`, `certs`, `key` and `crl` for modifications at this interval,
+    /// and reload them. Disabled by default, see `ServerCommand::ReloadTls`.
+    pub tls_reload_interval_ms: Option<u64>,
+    /// Serve metrics over HTTP, see `MetricsConfig`.
+    pub metrics: Option<MetricsConfig>,
+}
+
+/// Where to read a password from, either `env` or `file`.
+///
+/// Certificate and key files are detected as PEM, DER or PKCS#12 from their content.
+#[derive(Deserialize)]
+pub struct PasswordConfig {
+    /// Environment variable holding the password.
+    pub env: Option<String>,
+    /// File holding the password, trailing newlines are ignored.
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+pub struct MetricsConfig {
+    /// Local address to serve the Prometheus text format on, at any path.
+    pub addr: SocketAddr,
 }
 
 impl ServerConfig {
     /// The load function supports `toml`, `json`, `yaml` and many more formats.
     /// See [config-rs](https://docs.rs/config/latest/config/).
-    pub fn load<S: AsRef<str>>(name: S) -> std::io::Result<ServerConfig> {
-        let config = config::Config::builder()
+    pub fn load<S: AsRef<str>>(name: S) -> Result<ServerConfig, QuicnetError> {
+        let error = |source| QuicnetError::ConfigParse {
+            path: name.as_ref().to_string(),
+            source,
+        };
+        config::Config::builder()
             .add_source(config::File::with_name(name.as_ref()))
             .build()
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("error loading config {e}"),
-                )
-            })?;
-        config.try_deserialize().map_err(|e| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("error deserializing config {e}"),
-            )
-        })
+            .map_err(error)?
+            .try_deserialize()
+            .map_err(error)
+    }
+}
+
+impl PasswordConfig {
+    /// Read the password, the error is the reason it could not be read.
+    pub(crate) fn read(&self) -> Result<String, String> {
+        match (&self.env, &self.file) {
+            (Some(_), Some(_)) => Err("password env conflicts with password file".to_string()),
+            (Some(var), None) => std::env::var(var).map_err(|e| format!("password env {var}: {e}")),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map(|password| password.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|e| format!("password file {}: {e}", path.display())),
+            (None, None) => Err("password requires env or file".to_string()),
+        }
     }
 }
 
@@ -90,12 +149,56 @@ mod test_config {
         assert_eq!(name_b, NAME_B);
     }
 
+    #[test]
+    fn test_passwords() {
+        let unset = || PasswordConfig {
+            env: Some("QUICNET_TEST_UNSET_PASSWORD".to_string()),
+            file: None,
+        };
+        let password = || PasswordConfig {
+            env: None,
+            file: Some(PathBuf::from("./certs/ddpwuxrmp.uk/password.txt")),
+        };
+        let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+        // not read for PEM files
+        config.ca_password = Some(unset());
+        config.certs_password = Some(unset());
+        config.key_password = Some(unset());
+        quic::default_config(&config, Default::default(), Default::default())
+            .expect("failed to build config with unset passwords");
+
+        config.certs = PathBuf::from("./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.encrypted.p12");
+        config.key = PathBuf::from("./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.encrypted.key");
+        config.certs_password = Some(password());
+        assert!(matches!(
+            quic::default_config(&config, Default::default(), Default::default()),
+            Err(QuicnetError::KeyLoad { reason, .. }) if reason.contains("QUICNET_TEST_UNSET_PASSWORD")
+        ));
+        config.key_password = Some(password());
+        quic::default_config(&config, Default::default(), Default::default())
+            .expect("failed to build config from encrypted files");
+
+        config.key_password = Some(PasswordConfig {
+            env: unset().env,
+            file: password().file,
+        });
+        assert!(matches!(
+            quic::default_config(&config, Default::default(), Default::default()),
+            Err(QuicnetError::KeyLoad { reason, .. }) if reason.contains("conflicts")
+        ));
+    }
+
     // helper functions
 
     fn make_server(config_file: &str) -> (ServerConfig, quinn::Endpoint) {
         let server_conf = ServerConfig::load(config_file).expect("failed to load server config");
-        let (server_config, client_config) =
-            quic::default_config(&server_conf).expect("failed to build server config");
+        let whitelist = whitelist::Whitelist::new(server_conf.whitelist.clone());
+        let (server_config, client_config) = quic::default_config(
+            &server_conf,
+            std::sync::Arc::new(whitelist),
+            Default::default(),
+        )
+        .expect("failed to build server config");
         let mut server =
             quinn::Endpoint::server(server_config, server_conf.addr).expect("init server failed");
         server.set_default_client_config(client_config);
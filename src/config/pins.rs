@@ -0,0 +1,316 @@
+use super::{domain_name::DomainPattern, tls::cert_dns_names};
+use crate::error::QuicnetError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de::Visitor, Deserialize, Deserializer};
+use std::{collections::HashMap, fmt::Display, str::FromStr, time::SystemTime};
+use webpki::DnsName;
+use x509_parser::{error::X509Error, time::ASN1Time};
+
+/// Settings of a single peer.
+#[derive(Clone, Deserialize)]
+pub struct PeerConfig {
+    /// Name of the peer, as in its certificate.
+    pub domain: DomainPattern,
+    /// Accepted public keys of the peer, see `SpkiPin`. The certificate of
+    /// the peer must carry one of them, in addition to being trusted by `ca`.
+    #[serde(default)]
+    pub pins: Vec<SpkiPin>,
+    /// Accept the peer on a matching pin alone, without validating the chain
+    /// against `ca`, e.g. for self-signed certificates. Requires `pins`.
+    #[serde(default)]
+    pub pin_only: bool,
+}
+
+/// SHA-256 hash of the DER-encoded SubjectPublicKeyInfo of a certificate,
+/// written as `sha256/<base64>` like HPKP pins, e.g. from
+///
+/// ```sh
+/// openssl x509 -in peer.crt -pubkey -noout | openssl pkey -pubin -outform der \
+///     | openssl dgst -sha256 -binary | base64
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// The pin of the public key of `cert`.
+    pub fn of(cert: &rustls::Certificate) -> Result<Self, X509Error> {
+        let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).map_err(|e| match e {
+            x509_parser::nom::Err::Error(e) | x509_parser::nom::Err::Failure(e) => e,
+            x509_parser::nom::Err::Incomplete(_) => X509Error::InvalidCertificate,
+        })?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, cert.public_key().raw);
+        let mut pin = [0; 32];
+        pin.copy_from_slice(digest.as_ref());
+        Ok(SpkiPin(pin))
+    }
+}
+
+/// Invalid pin.
+#[derive(Debug)]
+pub struct InvalidPin(String);
+
+impl Display for InvalidPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid pin {:?}, expected sha256/<base64>", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPin {}
+
+impl FromStr for SpkiPin {
+    type Err = InvalidPin;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || InvalidPin(s.to_string());
+        let hash = s.strip_prefix("sha256/").ok_or_else(error)?;
+        let hash = STANDARD.decode(hash).map_err(|_| error())?;
+        Ok(SpkiPin(hash.try_into().map_err(|_| error())?))
+    }
+}
+
+impl Display for SpkiPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256/{}", STANDARD.encode(self.0))
+    }
+}
+
+struct SpkiPinVisitor;
+
+impl<'de> Visitor<'de> for SpkiPinVisitor {
+    type Value = SpkiPin;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a pin `sha256/<base64>`")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        v.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for SpkiPin {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_string(SpkiPinVisitor)
+    }
+}
+
+/// Outcome of a successful pin check.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Pinned {
+    /// No name of the certificate is pinned.
+    No,
+    /// The certificate carries the pinned key of its pinned names.
+    Yes,
+    /// All names of the certificate are pin-only peers with matching keys,
+    /// validation against the CA is skipped.
+    PinOnly,
+}
+
+/// The certificate names a pinned peer, but does not carry one of its keys.
+#[derive(Debug)]
+pub(crate) enum PinError {
+    Mismatch(DnsName),
+    Certificate(X509Error),
+}
+
+impl Display for PinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PinError::Mismatch(peer) => write!(
+                f,
+                "public key does not match the pins of {}",
+                AsRef::<str>::as_ref(peer)
+            ),
+            PinError::Certificate(e) => write!(f, "invalid certificate: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+struct PeerPins {
+    pins: Vec<SpkiPin>,
+    pin_only: bool,
+}
+
+/// Pins of the configured peers, by lowercase name.
+#[derive(Default)]
+pub(crate) struct Pins(HashMap<String, PeerPins>);
+
+impl Pins {
+    pub(crate) fn new(peers: &[PeerConfig]) -> Result<Self, QuicnetError> {
+        let error = |reason: String| QuicnetError::InvalidConfig {
+            field: "peers",
+            reason,
+        };
+        let mut pins = HashMap::new();
+        for peer in peers {
+            let DomainPattern::Exact(domain) = &peer.domain else {
+                return Err(error(format!("{} is not a domain name", peer.domain)));
+            };
+            if peer.pin_only && peer.pins.is_empty() {
+                return Err(error(format!("pin_only without pins for {}", peer.domain)));
+            }
+            if peer.pins.is_empty() {
+                continue;
+            }
+            let previous = pins.insert(
+                AsRef::<str>::as_ref(domain).to_ascii_lowercase(),
+                PeerPins {
+                    pins: peer.pins.clone(),
+                    pin_only: peer.pin_only,
+                },
+            );
+            if previous.is_some() {
+                return Err(error(format!("duplicate peer {}", peer.domain)));
+            }
+        }
+        Ok(Pins(pins))
+    }
+
+    /// Check `cert` against the pins of the names it claims,
+    /// its DNS names and `also`, e.g. the name it was dialed or matched by.
+    pub(crate) fn check(
+        &self,
+        cert: &rustls::Certificate,
+        also: Option<&DnsName>,
+    ) -> Result<Pinned, PinError> {
+        if self.0.is_empty() {
+            return Ok(Pinned::No);
+        }
+        let mut names = cert_dns_names(cert).map_err(PinError::Certificate)?;
+        names.extend(also.cloned());
+        let pinned: Vec<_> = names
+            .iter()
+            .filter_map(|name| {
+                let key = AsRef::<str>::as_ref(name).to_ascii_lowercase();
+                self.0.get(&key).map(|pins| (name, pins))
+            })
+            .collect();
+        if pinned.is_empty() {
+            return Ok(Pinned::No);
+        }
+        let pin = SpkiPin::of(cert).map_err(PinError::Certificate)?;
+        if let Some((name, _)) = pinned.iter().find(|(_, pins)| !pins.pins.contains(&pin)) {
+            return Err(PinError::Mismatch((*name).clone()));
+        }
+        // a certificate may not name unpinned peers without CA validation
+        if pinned.len() == names.len() && pinned.iter().all(|(_, pins)| pins.pin_only) {
+            Ok(Pinned::PinOnly)
+        } else {
+            Ok(Pinned::Yes)
+        }
+    }
+}
+
+/// Whether `now` is within the validity period of `cert`,
+/// checked for pin-only peers instead of webpki.
+pub(crate) fn check_validity(
+    cert: &rustls::Certificate,
+    now: SystemTime,
+) -> Result<(), webpki::Error> {
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(&cert.0).map_err(|_| webpki::Error::BadDer)?;
+    let now = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .and_then(|d| ASN1Time::from_timestamp(d.as_secs() as i64).ok())
+        .ok_or(webpki::Error::BadDerTime)?;
+    let validity = cert.validity();
+    if now < validity.not_before {
+        Err(webpki::Error::CertNotValidYet)
+    } else if now > validity.not_after {
+        Err(webpki::Error::CertExpired)
+    } else {
+        Ok(())
+    }
+}
+
+impl From<PinError> for rustls::Error {
+    fn from(error: PinError) -> Self {
+        match error {
+            PinError::Mismatch(_) => rustls::CertificateError::ApplicationVerificationFailure,
+            PinError::Certificate(_) => rustls::CertificateError::BadEncoding,
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod pins_tests {
+    use super::*;
+    use crate::config::tls::load_certificates;
+
+    const TEST_CRT: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.crt";
+    const OTHER_CRT: &str = "./certs/rehdhssj.cn/rehdhssj.cn.crt";
+
+    fn peer(domain: &str, pins: Vec<SpkiPin>, pin_only: bool) -> PeerConfig {
+        PeerConfig {
+            domain: domain.parse().unwrap(),
+            pins,
+            pin_only,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let cert = &load_certificates(TEST_CRT, None).expect("failed to load certs")[0];
+        let pin = SpkiPin::of(cert).expect("invalid certificate");
+        assert_eq!(pin.to_string().parse::<SpkiPin>().unwrap(), pin);
+        for invalid in [
+            "",
+            "sha256/",
+            "sha1/AAAA",
+            "sha256/AAAA",
+            "sha256/not base64",
+        ] {
+            assert!(invalid.parse::<SpkiPin>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let cert = &load_certificates(TEST_CRT, None).expect("failed to load certs")[0];
+        let other = &load_certificates(OTHER_CRT, None).expect("failed to load certs")[0];
+        let pin = SpkiPin::of(cert).unwrap();
+
+        let pins = Pins::new(&[peer("ddpwuxrmp.uk", vec![pin], false)]).unwrap();
+        assert_eq!(pins.check(cert, None).unwrap(), Pinned::Yes);
+        assert_eq!(pins.check(other, None).unwrap(), Pinned::No);
+        // the certificate of another peer, presented under the pinned name
+        let name = DnsName::from(webpki::DnsNameRef::try_from_ascii_str("ddpwuxrmp.uk").unwrap());
+        assert!(matches!(
+            pins.check(other, Some(&name)),
+            Err(PinError::Mismatch(_))
+        ));
+
+        let pins = Pins::new(&[peer("DDPWUXRMP.uk", vec![pin], true)]).unwrap();
+        assert_eq!(pins.check(cert, None).unwrap(), Pinned::PinOnly);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let pin = "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+            .parse()
+            .unwrap();
+        for peers in [
+            vec![peer("*.example", vec![pin], false)],
+            vec![peer("a.example", vec![], true)],
+            vec![
+                peer("a.example", vec![pin], false),
+                peer("A.example", vec![pin], true),
+            ],
+        ] {
+            assert!(matches!(
+                Pins::new(&peers),
+                Err(QuicnetError::InvalidConfig { .. })
+            ));
+        }
+    }
+}
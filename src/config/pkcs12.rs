@@ -0,0 +1,506 @@
+//! Reader of PKCS#12 bundles (RFC 7292) and PKCS#8 encrypted private keys.
+//!
+//! Keys and certificates may be encrypted with PBES2, as exported by openssl 3
+//! by default, with AES and PBKDF2-HMAC-SHA-256 or scrypt. The password also
+//! verifies the integrity MAC, with HMAC-SHA-256.
+//!
+//! The `legacy-pkcs12` feature adds the legacy PKCS#12 schemes of 3DES and RC2,
+//! PBES2 with 3DES or with HMAC-SHA-1, -224, -384 and -512, and MACs with
+//! HMAC-SHA-1, -384 and -512, as exported with `-legacy` or by older tools.
+#[cfg(feature = "legacy-pkcs12")]
+use cbc::cipher::{block_padding::Pkcs7, BlockCipher, BlockDecryptMut, KeyInit, KeyIvInit};
+use cms::{content_info::ContentInfo, encrypted_data::EncryptedData};
+use der::{
+    asn1::{ObjectIdentifier, OctetString},
+    oid::AssociatedOid,
+    Any, Decode, Encode, Header, SliceReader, Tag,
+};
+use hmac::{
+    digest::{core_api::BlockSizeUser, Digest, FixedOutputReset},
+    Mac, SimpleHmac,
+};
+use pkcs12::{
+    cert_type::CertBag,
+    kdf::{derive_key, Pkcs12KeyType},
+    mac_data::MacData,
+    pbe_params::EncryptedPrivateKeyInfo,
+    pfx::{Pfx, Version},
+    safe_bag::SafeContents,
+};
+use pkcs5::pbes2::{self, EncryptionScheme, Pbkdf2Prf};
+use spki::AlgorithmIdentifierOwned;
+use std::fmt::Display;
+
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+const ID_ENCRYPTED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.6");
+const ID_SHA1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const ID_DES_EDE3_CBC: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.3.7");
+
+/// Algorithms only supported with the `legacy-pkcs12` feature,
+/// other than the PRFs of PBKDF2.
+const LEGACY_ALGORITHMS: [ObjectIdentifier; 8] = [
+    ID_SHA1,
+    sha2::Sha384::OID,
+    sha2::Sha512::OID,
+    ID_DES_EDE3_CBC,
+    pkcs12::PKCS_12_PBE_WITH_SHAAND3_KEY_TRIPLE_DES_CBC,
+    pkcs12::PKCS_12_PBE_WITH_SHAAND2_KEY_TRIPLE_DES_CBC,
+    pkcs12::PKCS_12_PBE_WITH_SHAAND128_BIT_RC2_CBC,
+    pkcs12::PKCS_12_PBEWITH_SHAAND40_BIT_RC2_CBC,
+];
+
+/// Limit of the iterations of a key derivation, and of the product of the
+/// scrypt parameters N, r and p, which are read from the file.
+///
+/// Well above the 2048 iterations of openssl and the 600,000 recommended
+/// for PBKDF2-HMAC-SHA256, it bounds the time spent on a crafted file.
+const MAX_ITERATIONS: u64 = 1 << 20;
+
+/// The certificates and private keys of a bundle, in order.
+#[derive(Default)]
+pub(crate) struct Bundle {
+    pub certs: Vec<rustls::Certificate>,
+    pub keys: Vec<rustls::PrivateKey>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Pkcs12Error {
+    /// Not DER, or not the expected structure.
+    Malformed,
+    /// The integrity MAC or the decryption failed with the password.
+    WrongPassword,
+    /// An encryption or MAC algorithm other than those of the module docs.
+    Unsupported(ObjectIdentifier),
+    /// A legacy algorithm, without the `legacy-pkcs12` feature.
+    Legacy(ObjectIdentifier),
+    /// An iteration count, or scrypt cost, above `MAX_ITERATIONS` or below one.
+    Iterations(i64),
+}
+
+impl Display for Pkcs12Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pkcs12Error::Malformed => write!(f, "malformed PKCS#12 bundle or encrypted key"),
+            Pkcs12Error::WrongPassword => {
+                write!(
+                    f,
+                    "MAC verification or decryption failed, wrong or missing password"
+                )
+            }
+            Pkcs12Error::Unsupported(oid) => write!(f, "unsupported algorithm {oid}"),
+            Pkcs12Error::Legacy(oid) => write!(
+                f,
+                "legacy algorithm {oid} requires the legacy-pkcs12 feature"
+            ),
+            Pkcs12Error::Iterations(iterations) => write!(
+                f,
+                "key derivation with {iterations} iterations, requires 1 to {MAX_ITERATIONS}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Pkcs12Error {}
+
+impl From<der::Error> for Pkcs12Error {
+    fn from(_: der::Error) -> Self {
+        Pkcs12Error::Malformed
+    }
+}
+
+/// Whether `der` looks like a PKCS#12 bundle, rather than another DER structure.
+///
+/// Certificates and CRLs start with a SEQUENCE, private keys with version 0 or 1.
+pub(crate) fn is_pkcs12(der: &[u8]) -> bool {
+    let Ok(mut reader) = SliceReader::new(der) else {
+        return false;
+    };
+    matches!(Header::decode(&mut reader), Ok(header) if header.tag == Tag::Sequence)
+        && u8::decode(&mut reader) == Ok(3)
+}
+
+/// Whether `der` is a PKCS#8 `EncryptedPrivateKeyInfo`, rather than a private key.
+pub(crate) fn is_encrypted_key(der: &[u8]) -> bool {
+    EncryptedPrivateKeyInfo::from_der(der).is_ok()
+}
+
+/// Decrypt a PKCS#8 `EncryptedPrivateKeyInfo` into its private key.
+pub(crate) fn decrypt_key(der: &[u8], password: &str) -> Result<rustls::PrivateKey, Pkcs12Error> {
+    let key = EncryptedPrivateKeyInfo::from_der(der)?;
+    decrypt(
+        &key.encryption_algorithm,
+        key.encrypted_data.as_bytes(),
+        password,
+    )
+    .map(rustls::PrivateKey)
+}
+
+/// Parse a bundle, verifying its MAC with `password` if it has one,
+/// and decrypting its encrypted bags with it.
+pub(crate) fn parse(der: &[u8], password: &str) -> Result<Bundle, Pkcs12Error> {
+    let pfx = Pfx::from_der(der)?;
+    if pfx.version != Version::V3 {
+        return Err(Pkcs12Error::Malformed);
+    }
+    // public-key integrity mode is not supported
+    if pfx.auth_safe.content_type != ID_DATA {
+        return Err(Pkcs12Error::Unsupported(pfx.auth_safe.content_type));
+    }
+    let auth_safe = pfx.auth_safe.content.decode_as::<OctetString>()?;
+    if let Some(mac_data) = &pfx.mac_data {
+        verify_mac(mac_data, auth_safe.as_bytes(), password)?;
+    }
+    let mut bundle = Bundle::default();
+    for content_info in Vec::<ContentInfo>::from_der(auth_safe.as_bytes())? {
+        let safe_contents = match content_info.content_type {
+            ID_DATA => content_info
+                .content
+                .decode_as::<OctetString>()?
+                .into_bytes(),
+            ID_ENCRYPTED_DATA => {
+                let content = content_info
+                    .content
+                    .decode_as::<EncryptedData>()?
+                    .enc_content_info;
+                match content.encrypted_content {
+                    Some(data) => decrypt(&content.content_enc_alg, data.as_bytes(), password)?,
+                    None => continue,
+                }
+            }
+            other => return Err(Pkcs12Error::Unsupported(other)),
+        };
+        read_bags(&safe_contents, password, &mut bundle)?;
+    }
+    Ok(bundle)
+}
+
+fn read_bags(safe_contents: &[u8], password: &str, bundle: &mut Bundle) -> Result<(), Pkcs12Error> {
+    for bag in SafeContents::from_der(safe_contents)? {
+        // the value is explicitly tagged [0]
+        let value = Any::from_der(&bag.bag_value)?;
+        match bag.bag_id {
+            pkcs12::PKCS_12_KEY_BAG_OID => {
+                bundle.keys.push(rustls::PrivateKey(value.value().to_vec()))
+            }
+            pkcs12::PKCS_12_PKCS8_KEY_BAG_OID => {
+                bundle.keys.push(decrypt_key(value.value(), password)?)
+            }
+            pkcs12::PKCS_12_CERT_BAG_OID => {
+                let cert_bag = CertBag::from_der(value.value())?;
+                if cert_bag.cert_id == pkcs12::PKCS_12_X509_CERT_OID {
+                    bundle
+                        .certs
+                        .push(rustls::Certificate(cert_bag.cert_value.into_bytes()));
+                }
+            }
+            // e.g. CRL, secret and nested bags
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn verify_mac(mac_data: &MacData, auth_safe: &[u8], password: &str) -> Result<(), Pkcs12Error> {
+    let rounds = iterations(mac_data.iterations.into())?;
+    let verify = match mac_data.mac.algorithm.oid {
+        sha2::Sha256::OID => verify_hmac::<sha2::Sha256>,
+        #[cfg(feature = "legacy-pkcs12")]
+        ID_SHA1 => verify_hmac::<sha1::Sha1>,
+        #[cfg(feature = "legacy-pkcs12")]
+        sha2::Sha384::OID => verify_hmac::<sha2::Sha384>,
+        #[cfg(feature = "legacy-pkcs12")]
+        sha2::Sha512::OID => verify_hmac::<sha2::Sha512>,
+        other => return Err(unsupported(other)),
+    };
+    let mut passwords = vec![bmp_string(password)];
+    // an empty password may also be encoded without the terminator
+    if password.is_empty() {
+        passwords.push(Vec::new());
+    }
+    passwords
+        .iter()
+        .any(|password| verify(mac_data, auth_safe, password, rounds))
+        .then_some(())
+        .ok_or(Pkcs12Error::WrongPassword)
+}
+
+fn verify_hmac<D>(mac_data: &MacData, auth_safe: &[u8], password: &[u8], rounds: i32) -> bool
+where
+    D: Digest + FixedOutputReset + BlockSizeUser + Clone,
+{
+    let key = derive_key::<D>(
+        password,
+        mac_data.mac_salt.as_bytes(),
+        Pkcs12KeyType::Mac,
+        rounds,
+        <D as Digest>::output_size(),
+    );
+    let Ok(mut hmac) = <SimpleHmac<D> as Mac>::new_from_slice(&key) else {
+        return false;
+    };
+    hmac.update(auth_safe);
+    hmac.verify_slice(mac_data.mac.digest.as_bytes()).is_ok()
+}
+
+/// Decrypt `data`, encrypted with PBES2 or, with the `legacy-pkcs12` feature,
+/// a legacy PKCS#12 scheme.
+fn decrypt(
+    algorithm: &AlgorithmIdentifierOwned,
+    data: &[u8],
+    password: &str,
+) -> Result<Vec<u8>, Pkcs12Error> {
+    let parameters = algorithm
+        .parameters
+        .as_ref()
+        .ok_or(Pkcs12Error::Malformed)?
+        .to_der()?;
+    match algorithm.oid {
+        pbes2::PBES2_OID => {
+            let parameters = pbes2::Parameters::from_der(&parameters)
+                .map_err(|_| unsupported_pbes2(&parameters))?;
+            if !matches!(
+                parameters.encryption,
+                EncryptionScheme::Aes128Cbc { .. }
+                    | EncryptionScheme::Aes192Cbc { .. }
+                    | EncryptionScheme::Aes256Cbc { .. }
+            ) {
+                legacy(parameters.encryption.oid())?;
+            }
+            if let Some(pbkdf2) = parameters.kdf.pbkdf2() {
+                if pbkdf2.prf != Pbkdf2Prf::HmacWithSha256 {
+                    legacy(pbkdf2.prf.oid())?;
+                }
+                iterations(pbkdf2.iteration_count.into())?;
+            }
+            if let Some(scrypt) = parameters.kdf.scrypt() {
+                let cost = scrypt.cost_parameter.saturating_mul(
+                    u64::from(scrypt.block_size) * u64::from(scrypt.parallelization),
+                );
+                iterations(cost.try_into().unwrap_or(i64::MAX))?;
+            }
+            parameters
+                .decrypt(password, data)
+                .map_err(|_| Pkcs12Error::WrongPassword)
+        }
+        #[cfg(feature = "legacy-pkcs12")]
+        pkcs12::PKCS_12_PBE_WITH_SHAAND3_KEY_TRIPLE_DES_CBC => {
+            decrypt_pbe::<des::TdesEde3>(&parameters, data, password, 24)
+        }
+        #[cfg(feature = "legacy-pkcs12")]
+        pkcs12::PKCS_12_PBE_WITH_SHAAND2_KEY_TRIPLE_DES_CBC => {
+            decrypt_pbe::<des::TdesEde2>(&parameters, data, password, 16)
+        }
+        #[cfg(feature = "legacy-pkcs12")]
+        pkcs12::PKCS_12_PBE_WITH_SHAAND128_BIT_RC2_CBC => {
+            decrypt_pbe::<rc2::Rc2>(&parameters, data, password, 16)
+        }
+        #[cfg(feature = "legacy-pkcs12")]
+        pkcs12::PKCS_12_PBEWITH_SHAAND40_BIT_RC2_CBC => {
+            decrypt_pbe::<rc2::Rc2>(&parameters, data, password, 5)
+        }
+        other => Err(unsupported(other)),
+    }
+}
+
+/// The error of PBES2 parameters that failed to parse,
+/// as the encryption scheme, e.g. 3DES, may only be known with a feature.
+fn unsupported_pbes2(parameters: &[u8]) -> Pkcs12Error {
+    // the key derivation and encryption scheme, both algorithm identifiers
+    match Vec::<AlgorithmIdentifierOwned>::from_der(parameters).as_deref() {
+        Ok([_, encryption]) => unsupported(encryption.oid),
+        _ => Pkcs12Error::Unsupported(pbes2::PBES2_OID),
+    }
+}
+
+/// The error of an algorithm that is not supported,
+/// `Legacy` if the `legacy-pkcs12` feature would support it.
+fn unsupported(oid: ObjectIdentifier) -> Pkcs12Error {
+    if cfg!(not(feature = "legacy-pkcs12")) && LEGACY_ALGORITHMS.contains(&oid) {
+        Pkcs12Error::Legacy(oid)
+    } else {
+        Pkcs12Error::Unsupported(oid)
+    }
+}
+
+/// Fail with `Legacy` unless the `legacy-pkcs12` feature is enabled.
+fn legacy(oid: ObjectIdentifier) -> Result<(), Pkcs12Error> {
+    if cfg!(feature = "legacy-pkcs12") {
+        Ok(())
+    } else {
+        Err(Pkcs12Error::Legacy(oid))
+    }
+}
+
+/// Decrypt with the key and IV derived with SHA-1 as in RFC 7292 appendix B.2.
+#[cfg(feature = "legacy-pkcs12")]
+fn decrypt_pbe<C>(
+    parameters: &[u8],
+    data: &[u8],
+    password: &str,
+    key_len: usize,
+) -> Result<Vec<u8>, Pkcs12Error>
+where
+    C: BlockCipher + BlockDecryptMut + KeyInit,
+{
+    let parameters = pkcs12::pbe_params::Pkcs12PbeParams::from_der(parameters)?;
+    let rounds = iterations(parameters.iterations.into())?;
+    let password = bmp_string(password);
+    let salt = parameters.salt.as_bytes();
+    let key = derive_key::<sha1::Sha1>(
+        &password,
+        salt,
+        Pkcs12KeyType::EncryptionKey,
+        rounds,
+        key_len,
+    );
+    let iv = derive_key::<sha1::Sha1>(&password, salt, Pkcs12KeyType::Iv, rounds, C::block_size());
+    cbc::Decryptor::<C>::new_from_slices(&key, &iv)
+        .map_err(|_| Pkcs12Error::Malformed)?
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| Pkcs12Error::WrongPassword)
+}
+
+/// `iterations` as the rounds of a key derivation, if within `MAX_ITERATIONS`.
+fn iterations(iterations: i64) -> Result<i32, Pkcs12Error> {
+    if (1..=MAX_ITERATIONS as i64).contains(&iterations) {
+        Ok(iterations as i32)
+    } else {
+        Err(Pkcs12Error::Iterations(iterations))
+    }
+}
+
+/// Null-terminated UTF-16BE, the password encoding of PKCS#12.
+fn bmp_string(password: &str) -> Vec<u8> {
+    password
+        .encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+#[cfg(test)]
+mod pkcs12_tests {
+    use super::*;
+
+    const BUNDLE: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.p12";
+    const BUNDLE_SHA1: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.sha1.p12";
+    const BUNDLE_ENCRYPTED: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.encrypted.p12";
+    const BUNDLE_LEGACY: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.legacy.p12";
+    const ENCRYPTED_KEY: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.encrypted.key.der";
+    const LEGACY_KEY: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.legacy.key.der";
+    const DES3_KEY: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.des3.key.der";
+    const SHA1_KEY: &str = "./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.sha1.key.der";
+    const PASSWORD: &str = "quicnet";
+
+    #[test]
+    fn test_parse() {
+        let der = std::fs::read(BUNDLE).unwrap();
+        assert!(is_pkcs12(&der));
+        let bundle = parse(&der, PASSWORD).expect("failed to parse bundle");
+        // the certificate and the CA
+        assert_eq!(bundle.certs.len(), 2);
+        assert_eq!(bundle.keys.len(), 1);
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let der = std::fs::read(BUNDLE).unwrap();
+        let key = parse(&der, PASSWORD).expect("failed to parse bundle").keys;
+        // openssl defaults, PBES2 with AES-256 and PBKDF2
+        let der = std::fs::read(BUNDLE_ENCRYPTED).unwrap();
+        assert!(is_pkcs12(&der));
+        let bundle = parse(&der, PASSWORD).expect("failed to parse encrypted bundle");
+        assert_eq!(bundle.certs.len(), 1);
+        assert!(bundle.keys == key);
+        assert_eq!(parse(&der, "wrong").err(), Some(Pkcs12Error::WrongPassword));
+
+        let der = std::fs::read(ENCRYPTED_KEY).unwrap();
+        assert!(is_encrypted_key(&der));
+        assert!(decrypt_key(&der, PASSWORD) == Ok(key[0].clone()));
+        assert_eq!(decrypt_key(&der, "wrong"), Err(Pkcs12Error::WrongPassword));
+    }
+
+    #[cfg(feature = "legacy-pkcs12")]
+    #[test]
+    fn test_legacy() {
+        let der = std::fs::read(BUNDLE).unwrap();
+        let key = parse(&der, PASSWORD).expect("failed to parse bundle").keys;
+        let der = std::fs::read(BUNDLE_SHA1).unwrap();
+        let bundle = parse(&der, PASSWORD).expect("failed to parse SHA-1 bundle");
+        assert_eq!(bundle.certs.len(), 1);
+        assert!(bundle.keys == key);
+
+        // 3DES keys and RC2 certificates, with `-legacy`
+        let der = std::fs::read(BUNDLE_LEGACY).unwrap();
+        let bundle = parse(&der, PASSWORD).expect("failed to parse legacy bundle");
+        assert_eq!(bundle.certs.len(), 1);
+        assert!(bundle.keys == key);
+        assert_eq!(parse(&der, "wrong").err(), Some(Pkcs12Error::WrongPassword));
+
+        for path in [LEGACY_KEY, DES3_KEY, SHA1_KEY] {
+            let der = std::fs::read(path).unwrap();
+            assert!(decrypt_key(&der, PASSWORD) == Ok(key[0].clone()), "{path}");
+        }
+    }
+
+    #[cfg(not(feature = "legacy-pkcs12"))]
+    #[test]
+    fn test_legacy_unsupported() {
+        for path in [BUNDLE_SHA1, BUNDLE_LEGACY] {
+            let der = std::fs::read(path).unwrap();
+            assert_eq!(
+                parse(&der, PASSWORD).err(),
+                Some(Pkcs12Error::Legacy(ID_SHA1))
+            );
+        }
+        let cases = [
+            (
+                LEGACY_KEY,
+                pkcs12::PKCS_12_PBE_WITH_SHAAND3_KEY_TRIPLE_DES_CBC,
+            ),
+            (DES3_KEY, ID_DES_EDE3_CBC),
+            (SHA1_KEY, Pbkdf2Prf::HmacWithSha1.oid()),
+        ];
+        for (path, oid) in cases {
+            let der = std::fs::read(path).unwrap();
+            assert_eq!(decrypt_key(&der, PASSWORD), Err(Pkcs12Error::Legacy(oid)));
+        }
+        assert_eq!(
+            Pkcs12Error::Legacy(ID_SHA1).to_string(),
+            "legacy algorithm 1.3.14.3.2.26 requires the legacy-pkcs12 feature"
+        );
+    }
+
+    #[test]
+    fn test_iterations() {
+        assert_eq!(iterations(2048), Ok(2048));
+        assert_eq!(iterations(0), Err(Pkcs12Error::Iterations(0)));
+        assert_eq!(iterations(-1), Err(Pkcs12Error::Iterations(-1)));
+        assert_eq!(
+            iterations(i32::MAX.into()),
+            Err(Pkcs12Error::Iterations(i32::MAX.into()))
+        );
+        assert_eq!(
+            iterations(u32::MAX.into()),
+            Err(Pkcs12Error::Iterations(u32::MAX.into()))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let der = std::fs::read(BUNDLE).unwrap();
+        assert_eq!(parse(&der, "wrong").err(), Some(Pkcs12Error::WrongPassword));
+        assert_eq!(parse(&der, "").err(), Some(Pkcs12Error::WrongPassword));
+        assert_eq!(
+            parse(&der[..der.len() - 1], PASSWORD).err(),
+            Some(Pkcs12Error::Malformed)
+        );
+
+        let cert = std::fs::read("./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.der").unwrap();
+        assert!(!is_pkcs12(&cert));
+        let key = std::fs::read("./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.key.der").unwrap();
+        assert!(!is_pkcs12(&key));
+        assert!(!is_encrypted_key(&key));
+        assert!(!is_encrypted_key(&cert));
+    }
+}
@@ -1,21 +1,99 @@
 use super::{
-    tls::{build_crypto, load_certificates, load_private_key, load_whitelist},
+    crl::RevocationList,
+    pins::Pins,
+    tls::{build_crypto, load_certificates, load_private_key},
+    whitelist::Whitelist,
     ServerConfig,
 };
+use crate::{error::QuicnetError, metrics::Metrics};
+use quinn::{
+    congestion::{BbrConfig, CubicConfig, NewRenoConfig},
+    IdleTimeout, VarInt,
+};
+use serde::Deserialize;
 use std::{sync::Arc, time::Duration};
 
 pub const KEEP_ALIVE_INTERVAL: Option<Duration> = Some(Duration::from_secs(15));
+pub const DATAGRAM_RECEIVE_BUFFER_SIZE: Option<usize> = Some(1024 * 1024);
+pub const DATAGRAM_SEND_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Optional `[transport]` section of the config.
+///
+/// Unset fields keep the defaults of `default_transport_config`,
+/// or quinn's defaults for the parameters it does not set.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TransportConfig {
+    /// Keep-alive interval in milliseconds, 0 disables keep-alive.
+    pub keep_alive_interval_ms: Option<u64>,
+    /// Idle timeout in milliseconds, 0 disables the timeout.
+    pub max_idle_timeout_ms: Option<u64>,
+    /// Maximum number of concurrent bidirectional streams a peer may open.
+    pub max_concurrent_bidi_streams: Option<u64>,
+    /// Maximum number of concurrent unidirectional streams a peer may open.
+    pub max_concurrent_uni_streams: Option<u64>,
+    /// Receive window of each stream in bytes.
+    pub stream_receive_window: Option<u64>,
+    /// Receive window of each connection in bytes.
+    pub receive_window: Option<u64>,
+    /// Initial round-trip time estimate in milliseconds.
+    pub initial_rtt_ms: Option<u64>,
+    /// Datagram receive buffer size in bytes, 0 disables receiving datagrams.
+    pub datagram_receive_buffer_size: Option<usize>,
+    /// Datagram send buffer size in bytes.
+    pub datagram_send_buffer_size: Option<usize>,
+    /// Congestion controller, defaults to `cubic`.
+    pub congestion_control: Option<CongestionControl>,
+    /// Initial congestion window in bytes, for any controller.
+    pub initial_window: Option<u64>,
+    /// Window reduction factor on loss, for `newreno` only.
+    pub loss_reduction_factor: Option<f32>,
+}
+
+/// Congestion controllers shipped with quinn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CongestionControl {
+    #[default]
+    Cubic,
+    #[serde(alias = "new_reno")]
+    NewReno,
+    Bbr,
+}
+
+impl CongestionControl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CongestionControl::Cubic => "cubic",
+            CongestionControl::NewReno => "newreno",
+            CongestionControl::Bbr => "bbr",
+        }
+    }
+}
 
 /// Create a default configuation for the QUIC server.
 pub(crate) fn default_config(
     config: &ServerConfig,
-) -> std::io::Result<(quinn::ServerConfig, quinn::ClientConfig)> {
-    let ca = load_certificates(&config.ca)?;
-    let certs = load_certificates(&config.certs)?;
-    let key = load_private_key(&config.key)?;
-    let whitelist = load_whitelist(&config.whitelist);
-    let (server_crypto, client_crypto) = build_crypto(ca, whitelist, certs, key)?;
-    let transport_config = default_transport_config();
+    whitelist: Arc<Whitelist>,
+    metrics: Arc<Metrics>,
+) -> Result<(quinn::ServerConfig, quinn::ClientConfig), QuicnetError> {
+    let ca = load_certificates(&config.ca, config.ca_password.as_ref())?;
+    let certs = load_certificates(&config.certs, config.certs_password.as_ref())?;
+    let key = load_private_key(&config.key, config.key_password.as_ref())?;
+    let crl = match &config.crl {
+        Some(path) => RevocationList::load(path, &ca)?,
+        None => RevocationList::default(),
+    };
+    let pins = Pins::new(config.peers.as_deref().unwrap_or_default())?;
+    let (server_crypto, client_crypto) = build_crypto(
+        ca,
+        whitelist,
+        Arc::new(pins),
+        Arc::new(crl),
+        certs,
+        key,
+        metrics,
+    )?;
+    let transport_config = transport_config(config.transport.as_ref())?;
     let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
     let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
     server_config.transport_config(transport_config.clone());
@@ -27,9 +105,166 @@ pub(crate) fn default_config(
 ///
 /// - keep alive interval = 15 sec
 /// - disable idle timeout
-fn default_transport_config() -> Arc<quinn::TransportConfig> {
+/// - datagram receive and send buffers = 1 MiB
+fn default_transport_config() -> quinn::TransportConfig {
     let mut transport_config = quinn::TransportConfig::default();
     transport_config.keep_alive_interval(KEEP_ALIVE_INTERVAL);
     transport_config.max_idle_timeout(None);
-    Arc::new(transport_config)
+    transport_config.datagram_receive_buffer_size(DATAGRAM_RECEIVE_BUFFER_SIZE);
+    transport_config.datagram_send_buffer_size(DATAGRAM_SEND_BUFFER_SIZE);
+    transport_config
+}
+
+/// Apply the `[transport]` section over the default transport config.
+fn transport_config(
+    overrides: Option<&TransportConfig>,
+) -> Result<Arc<quinn::TransportConfig>, QuicnetError> {
+    let mut transport_config = default_transport_config();
+    let Some(overrides) = overrides else {
+        return Ok(Arc::new(transport_config));
+    };
+    if let Some(ms) = overrides.keep_alive_interval_ms {
+        transport_config.keep_alive_interval((ms > 0).then(|| Duration::from_millis(ms)));
+    }
+    if let Some(ms) = overrides.max_idle_timeout_ms {
+        let timeout = match ms {
+            0 => None,
+            ms => Some(IdleTimeout::from(var_int("max_idle_timeout_ms", ms)?)),
+        };
+        transport_config.max_idle_timeout(timeout);
+    }
+    if let Some(n) = overrides.max_concurrent_bidi_streams {
+        transport_config.max_concurrent_bidi_streams(var_int("max_concurrent_bidi_streams", n)?);
+    }
+    if let Some(n) = overrides.max_concurrent_uni_streams {
+        transport_config.max_concurrent_uni_streams(var_int("max_concurrent_uni_streams", n)?);
+    }
+    if let Some(size) = overrides.stream_receive_window {
+        transport_config.stream_receive_window(var_int("stream_receive_window", size)?);
+    }
+    if let Some(size) = overrides.receive_window {
+        transport_config.receive_window(var_int("receive_window", size)?);
+    }
+    if let Some(ms) = overrides.initial_rtt_ms {
+        transport_config.initial_rtt(Duration::from_millis(ms));
+    }
+    if let Some(size) = overrides.datagram_receive_buffer_size {
+        transport_config.datagram_receive_buffer_size((size > 0).then_some(size));
+    }
+    if let Some(size) = overrides.datagram_send_buffer_size {
+        transport_config.datagram_send_buffer_size(size);
+    }
+    set_congestion_controller(&mut transport_config, overrides)?;
+    Ok(Arc::new(transport_config))
+}
+
+fn set_congestion_controller(
+    transport_config: &mut quinn::TransportConfig,
+    overrides: &TransportConfig,
+) -> Result<(), QuicnetError> {
+    let controller = overrides.congestion_control.unwrap_or_default();
+    if overrides.loss_reduction_factor.is_some() && controller != CongestionControl::NewReno {
+        return Err(QuicnetError::InvalidConfig {
+            field: "loss_reduction_factor",
+            reason: format!("not supported by {}", controller.as_str()),
+        });
+    }
+    match controller {
+        CongestionControl::Cubic => {
+            let mut config = CubicConfig::default();
+            if let Some(window) = overrides.initial_window {
+                config.initial_window(window);
+            }
+            transport_config.congestion_controller_factory(Arc::new(config));
+        }
+        CongestionControl::NewReno => {
+            let mut config = NewRenoConfig::default();
+            if let Some(window) = overrides.initial_window {
+                config.initial_window(window);
+            }
+            if let Some(factor) = overrides.loss_reduction_factor {
+                if !(0.0..1.0).contains(&factor) {
+                    return Err(QuicnetError::InvalidConfig {
+                        field: "loss_reduction_factor",
+                        reason: format!("{factor} is not in [0, 1)"),
+                    });
+                }
+                config.loss_reduction_factor(factor);
+            }
+            transport_config.congestion_controller_factory(Arc::new(config));
+        }
+        CongestionControl::Bbr => {
+            let mut config = BbrConfig::default();
+            if let Some(window) = overrides.initial_window {
+                config.initial_window(window);
+            }
+            transport_config.congestion_controller_factory(Arc::new(config));
+        }
+    }
+    Ok(())
+}
+
+/// Transport parameters are encoded as QUIC variable-length integers (< 2^62).
+fn var_int(field: &'static str, value: u64) -> Result<VarInt, QuicnetError> {
+    VarInt::from_u64(value).map_err(|_| QuicnetError::InvalidConfig {
+        field,
+        reason: format!("{value} exceeds 2^62 - 1"),
+    })
+}
+
+#[cfg(test)]
+mod quic_tests {
+    use super::*;
+    use crate::config::ServerConfig;
+
+    #[test]
+    fn test_transport_section() {
+        let config = ServerConfig::load("data/config-transport.toml").expect("failed to load");
+        let transport = config.transport.as_ref().expect("no transport section");
+        assert_eq!(transport.keep_alive_interval_ms, Some(15_000));
+        assert_eq!(transport.max_idle_timeout_ms, Some(0));
+        assert_eq!(transport.datagram_receive_buffer_size, Some(1 << 20));
+        assert_eq!(transport.congestion_control, Some(CongestionControl::Bbr));
+        assert_eq!(transport.initial_window, Some(64 * 1024));
+        assert!(transport_config(Some(transport)).is_ok());
+        let config = ServerConfig::load("data/config-rehdhssj.toml").expect("failed to load");
+        assert!(config.transport.is_none());
+    }
+
+    #[test]
+    fn test_transport_config() {
+        assert!(transport_config(None).is_ok());
+        let overrides = TransportConfig {
+            keep_alive_interval_ms: Some(0),
+            max_idle_timeout_ms: Some(30_000),
+            max_concurrent_bidi_streams: Some(1000),
+            datagram_receive_buffer_size: Some(0),
+            congestion_control: Some(CongestionControl::Bbr),
+            initial_window: Some(64 * 1024),
+            ..Default::default()
+        };
+        assert!(transport_config(Some(&overrides)).is_ok());
+        let overrides = TransportConfig {
+            loss_reduction_factor: Some(0.7),
+            ..Default::default()
+        };
+        assert!(matches!(
+            transport_config(Some(&overrides)),
+            Err(QuicnetError::InvalidConfig {
+                field: "loss_reduction_factor",
+                ..
+            })
+        ));
+        let overrides = TransportConfig {
+            receive_window: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(matches!(
+            transport_config(Some(&overrides)),
+            Err(QuicnetError::InvalidConfig {
+                field: "receive_window",
+                ..
+            })
+        ));
+    }
 }
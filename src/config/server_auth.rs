@@ -0,0 +1,112 @@
+use super::{
+    client_auth::{intermediate_chain, pki_error, trust_roots, SUPPORTED_SIG_ALGS},
+    crl::RevocationList,
+    pins::{check_validity, Pinned, Pins},
+    whitelist::{permits_server, Whitelist},
+};
+use crate::{metrics::Metrics, server::Direction};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, CertificateError, ServerName,
+};
+use std::{sync::Arc, time::SystemTime};
+use webpki::{DnsName, DnsNameRef, TlsServerTrustAnchors};
+
+/// A `ServerCertVerifier` that will ensure that every server provides a trusted
+/// certificate for the dialed name, that the name is within the white list of
+/// domain names, that it carries the pinned key of pinned peers, and that no
+/// certificate of its chain is revoked.
+///
+/// The whitelist, pins and the signature algorithms are the same as for clients,
+/// see `AllowWhitelistAuthenticatedClient`.
+pub(crate) struct AuthenticatedServer {
+    roots: Vec<Certificate>,
+    whitelist: Arc<Whitelist>,
+    pins: Arc<Pins>,
+    crl: Arc<RevocationList>,
+    metrics: Arc<Metrics>,
+}
+
+impl AuthenticatedServer {
+    pub fn new(
+        roots: Vec<Certificate>,
+        whitelist: Arc<Whitelist>,
+        pins: Arc<Pins>,
+        crl: Arc<RevocationList>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            roots,
+            whitelist,
+            pins,
+            crl,
+            metrics,
+        }
+    }
+
+    #[inline(always)]
+    pub fn boxed(self) -> Arc<dyn ServerCertVerifier> {
+        Arc::new(self)
+    }
+}
+
+impl ServerCertVerifier for AuthenticatedServer {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // peers are always dialed by domain name
+        let ServerName::DnsName(server_name) = server_name else {
+            return Err(CertificateError::NotValidForName.into());
+        };
+        let domain: &str = server_name.as_ref();
+        let name = DnsNameRef::try_from_ascii_str(domain)
+            .map_err(|_| CertificateError::NotValidForName)?;
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref()).map_err(pki_error)?;
+        let pinned = self
+            .pins
+            .check(end_entity, Some(&DnsName::from(name)))
+            .map_err(|e| {
+                tracing::warn!("rejected server certificate of {domain}: {e}");
+                rustls::Error::from(e)
+            })?;
+
+        if pinned == Pinned::PinOnly {
+            check_validity(end_entity, now).map_err(pki_error)?;
+        } else {
+            let chain = intermediate_chain(intermediates);
+            let trust_roots = trust_roots(&self.roots)?;
+            let now =
+                webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+
+            let trusted_anchors = TlsServerTrustAnchors(&trust_roots);
+
+            cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS, &trusted_anchors, &chain, now)
+                .map_err(pki_error)?;
+        }
+        cert.verify_is_valid_for_dns_name(name).map_err(pki_error)?;
+
+        if self
+            .crl
+            .is_revoked(end_entity, intermediates)
+            .map_err(|_| CertificateError::BadEncoding)?
+        {
+            tracing::warn!("rejected revoked server certificate of {domain}");
+            self.metrics.revoked_certificate(Direction::Outbound);
+            return Err(CertificateError::Revoked.into());
+        }
+
+        if let Some(whitelist) = self.whitelist.load().as_ref() {
+            if !permits_server(whitelist, &DnsName::from(name), end_entity).map_err(pki_error)? {
+                tracing::warn!("rejected server {domain}, not in the whitelist");
+                return Err(CertificateError::NotValidForName.into());
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,190 @@
+use super::{
+    domain_name::DomainPattern,
+    tls::{cert_dns_names, match_certs_domain},
+};
+use arc_swap::ArcSwapOption;
+use std::sync::Arc;
+use webpki::DnsName;
+
+/// Domain patterns of permitted peers, shared by the certificate verifiers
+/// and the server, and replaceable at runtime.
+///
+/// `None` disables the whitelist, allowing every peer with a trusted certificate.
+/// Readers take a snapshot, so a handshake sees a single version of the list.
+#[derive(Default)]
+pub(crate) struct Whitelist(ArcSwapOption<Vec<DomainPattern>>);
+
+/// Change of the whitelist, see `ServerCommand::UpdateWhitelist`.
+#[derive(Clone, Debug)]
+pub enum WhitelistUpdate {
+    /// Permit more patterns. No effect while the whitelist is disabled.
+    Add(Vec<DomainPattern>),
+    /// Remove patterns, as written. No effect while the whitelist is disabled.
+    Remove(Vec<DomainPattern>),
+    /// Replace all patterns, `None` disables the whitelist.
+    Replace(Option<Vec<DomainPattern>>),
+}
+
+impl Whitelist {
+    pub(crate) fn new(domains: Option<Vec<DomainPattern>>) -> Self {
+        Self(ArcSwapOption::new(domains.map(Arc::new)))
+    }
+
+    /// The current domains.
+    pub(crate) fn load(&self) -> Option<Arc<Vec<DomainPattern>>> {
+        self.0.load_full()
+    }
+
+    /// Apply `update`, returning the new domains.
+    ///
+    /// Updates are applied one at a time by the command loop.
+    pub(crate) fn update(&self, update: WhitelistUpdate) -> Option<Arc<Vec<DomainPattern>>> {
+        let current = self.0.load();
+        let domains = match (update, current.as_deref()) {
+            (WhitelistUpdate::Replace(domains), _) => domains,
+            (WhitelistUpdate::Add(added), Some(domains)) => {
+                let mut domains = domains.clone();
+                for domain in added {
+                    if !domains.contains(&domain) {
+                        domains.push(domain);
+                    }
+                }
+                Some(domains)
+            }
+            (WhitelistUpdate::Remove(removed), Some(domains)) => Some(
+                domains
+                    .iter()
+                    .filter(|d| !removed.contains(d))
+                    .cloned()
+                    .collect(),
+            ),
+            (_, None) => None,
+        };
+        let domains = domains.map(Arc::new);
+        self.0.store(domains.clone());
+        domains
+    }
+}
+
+/// Whether `patterns` permit the peer presenting `cert` under any of its names,
+/// see `matched_name`.
+pub(crate) fn permits(
+    patterns: &Option<Arc<Vec<DomainPattern>>>,
+    cert: &rustls::Certificate,
+) -> bool {
+    patterns
+        .as_ref()
+        .is_none_or(|patterns| matches!(matched_name(patterns, cert), Ok(Some(_))))
+}
+
+/// Whether `patterns` permit the server `cert`, already verified for `name`,
+/// under that name.
+///
+/// The rules of `matched_name` apply: exact entries also match wildcard
+/// names in the certificate, other patterns only match names it lists.
+pub(crate) fn permits_server(
+    patterns: &[DomainPattern],
+    name: &DnsName,
+    cert: &rustls::Certificate,
+) -> Result<bool, webpki::Error> {
+    let mut matching = patterns.iter().filter(|p| p.matches(name)).peekable();
+    if matching.peek().is_none() {
+        return Ok(false);
+    }
+    if matching.any(|p| matches!(p, DomainPattern::Exact(_))) {
+        return Ok(true);
+    }
+    let names = cert_dns_names(cert).map_err(|_| webpki::Error::BadDer)?;
+    Ok(names
+        .iter()
+        .any(|n| DomainPattern::Exact(n.clone()).matches(name)))
+}
+
+/// The name `cert` is permitted under by `patterns`, if any.
+///
+/// Exact entries are tried first, in order, and also match wildcard names
+/// in the certificate. Otherwise the first DNS name of the certificate
+/// matching a pattern is used.
+pub(crate) fn matched_name(
+    patterns: &[DomainPattern],
+    cert: &rustls::Certificate,
+) -> Result<Option<DnsName>, webpki::Error> {
+    let exact: Vec<_> = patterns
+        .iter()
+        .filter_map(|p| match p {
+            DomainPattern::Exact(domain) => Some(domain.clone()),
+            _ => None,
+        })
+        .collect();
+    let matched = match_certs_domain(std::slice::from_ref(cert), &exact)?;
+    if let Some(name) = matched.into_iter().next() {
+        return Ok(Some(DnsName::from(name)));
+    }
+    let names = cert_dns_names(cert).map_err(|_| webpki::Error::BadDer)?;
+    Ok(names
+        .into_iter()
+        .find(|name| patterns.iter().any(|p| p.matches(name))))
+}
+
+#[cfg(test)]
+mod whitelist_tests {
+    use super::*;
+    use crate::config::tls::load_certificates;
+
+    fn dns_name(name: &str) -> DnsName {
+        DnsName::from(webpki::DnsNameRef::try_from_ascii_str(name).unwrap())
+    }
+
+    fn pattern(pattern: &str) -> DomainPattern {
+        pattern.parse().unwrap()
+    }
+
+    #[test]
+    fn test_update() {
+        let certs = load_certificates("./certs/rehdhssj.cn/rehdhssj.cn.crt", None)
+            .expect("failed to load certificate");
+        let (a, b) = (pattern("a.example"), pattern(".cn"));
+        let whitelist = Whitelist::new(Some(vec![a.clone()]));
+        let domains = whitelist.update(WhitelistUpdate::Add(vec![a.clone(), b.clone()]));
+        assert_eq!(domains.as_deref(), Some(&vec![a.clone(), b.clone()]));
+        let domains = whitelist.update(WhitelistUpdate::Remove(vec![a.clone()]));
+        assert!(permits(&domains, &certs[0]));
+        let domains = whitelist.update(WhitelistUpdate::Remove(vec![b.clone()]));
+        assert!(!permits(&domains, &certs[0]));
+        whitelist.update(WhitelistUpdate::Replace(None));
+        assert!(whitelist
+            .update(WhitelistUpdate::Add(vec![a.clone()]))
+            .is_none());
+        assert!(permits(&whitelist.load(), &certs[0]));
+    }
+
+    #[test]
+    fn test_matched_name() {
+        let certs = load_certificates("./certs/rehdhssj.cn/rehdhssj.cn.crt", None)
+            .expect("failed to load certificate");
+        let matched = |patterns: &[&str]| {
+            let patterns: Vec<_> = patterns.iter().map(|p| pattern(p)).collect();
+            matched_name(&patterns, &certs[0]).expect("invalid certificate")
+        };
+        let name = Some(dns_name("rehdhssj.cn"));
+        assert_eq!(matched(&["rehdhssj.cn"]), name);
+        assert_eq!(matched(&[".cn"]), name);
+        assert_eq!(matched(&["other.cn", "*.cn"]), name);
+        assert_eq!(matched(&["*.rehdhssj.cn", ".rehdhssj.cn", "cn"]), None);
+    }
+
+    #[test]
+    fn test_permits_server() {
+        let certs = load_certificates("./certs/rehdhssj.cn/rehdhssj.cn.crt", None)
+            .expect("failed to load certificate");
+        let permitted = |patterns: &[&str], name: &str| {
+            let patterns: Vec<_> = patterns.iter().map(|p| pattern(p)).collect();
+            permits_server(&patterns, &dns_name(name), &certs[0]).expect("invalid certificate")
+        };
+        assert!(permitted(&["rehdhssj.cn"], "rehdhssj.cn"));
+        assert!(permitted(&["other.cn", "*.cn"], "rehdhssj.cn"));
+        assert!(!permitted(&["ddpwuxrmp.uk"], "rehdhssj.cn"));
+        // the dialed name must be listed in the certificate, not only permitted
+        assert!(!permitted(&[".cn"], "other.cn"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::server::FrameError;
+use std::{fmt::Display, net::SocketAddr, path::PathBuf};
+use webpki::DnsName;
+
+/// Errors of loading the config, starting the server and connecting peers.
+///
+/// Each variant maps to a stable numeric code, see `QuicnetError::code`.
+#[derive(Debug)]
+pub enum QuicnetError {
+    /// The config file could not be read or deserialized.
+    ConfigParse {
+        path: String,
+        source: config::ConfigError,
+    },
+    /// A config value is out of range.
+    InvalidConfig { field: &'static str, reason: String },
+    /// A certificate file could not be read or parsed.
+    CertificateLoad { path: PathBuf, reason: String },
+    /// The private key file could not be read or parsed.
+    KeyLoad { path: PathBuf, reason: String },
+    /// A certificate revocation list could not be read, parsed or verified.
+    CrlLoad { path: PathBuf, reason: String },
+    /// The TLS configuration is invalid, e.g. an unparsable CA certificate or private key.
+    TlsBuild { reason: String },
+    /// The UDP socket could not be bound.
+    Bind {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+    /// A connection could not be established.
+    Connect {
+        addr: SocketAddr,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The certificate of a peer was rejected, or does not name the expected peer.
+    /// `peer` is `None` if the peer could not be identified.
+    PeerVerification {
+        addr: SocketAddr,
+        peer: Option<DnsName>,
+        reason: String,
+    },
+    /// An incoming frame exceeds the maximum frame size.
+    FrameTooLarge { peer: DnsName, source: FrameError },
+    /// The runtime or one of its threads could not be started.
+    Runtime(std::io::Error),
+}
+
+impl QuicnetError {
+    /// Stable numeric code of the error, as the `QuicnetStatus` returned by the C API.
+    pub fn code(&self) -> i32 {
+        crate::ffi::QuicnetStatus::from(self) as i32
+    }
+
+    /// The peer the error relates to, if known.
+    pub fn peer(&self) -> Option<&DnsName> {
+        match self {
+            QuicnetError::PeerVerification { peer, .. } => peer.as_ref(),
+            QuicnetError::FrameTooLarge { peer, .. } => Some(peer),
+            _ => None,
+        }
+    }
+}
+
+impl Display for QuicnetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuicnetError::ConfigParse { path, source } => {
+                write!(f, "error loading config {path}: {source}")
+            }
+            QuicnetError::InvalidConfig { field, reason } => {
+                write!(f, "invalid config value {field}: {reason}")
+            }
+            QuicnetError::CertificateLoad { path, reason } => {
+                write!(f, "error loading certificates {}: {reason}", path.display())
+            }
+            QuicnetError::KeyLoad { path, reason } => {
+                write!(f, "error loading private key {}: {reason}", path.display())
+            }
+            QuicnetError::CrlLoad { path, reason } => {
+                write!(f, "error loading CRL {}: {reason}", path.display())
+            }
+            QuicnetError::TlsBuild { reason } => write!(f, "invalid TLS config: {reason}"),
+            QuicnetError::Bind { addr, source } => write!(f, "failed to bind {addr}: {source}"),
+            QuicnetError::Connect { addr, source } => {
+                write!(f, "failed to connect {addr}: {source}")
+            }
+            QuicnetError::PeerVerification { addr, peer, reason } => match peer {
+                Some(peer) => write!(
+                    f,
+                    "failed to verify peer {} ({addr}): {reason}",
+                    AsRef::<str>::as_ref(peer)
+                ),
+                None => write!(f, "failed to verify peer {addr}: {reason}"),
+            },
+            QuicnetError::FrameTooLarge { peer, source } => write!(
+                f,
+                "rejected frame from {}: {source}",
+                AsRef::<str>::as_ref(peer)
+            ),
+            QuicnetError::Runtime(e) => write!(f, "failed to start runtime: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for QuicnetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QuicnetError::ConfigParse { source, .. } => Some(source),
+            QuicnetError::Bind { source, .. } => Some(source),
+            QuicnetError::Connect { source, .. } => Some(source.as_ref()),
+            QuicnetError::FrameTooLarge { source, .. } => Some(source),
+            QuicnetError::Runtime(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use std::{ffi::c_void, ptr};
+
+/// Releases the buffer owned by `owner`.
+pub type QuicnetReleaseFn = Option<unsafe extern "C" fn(owner: *mut c_void)>;
+
+/// A byte buffer passed across the FFI boundary without copying.
+///
+/// `data` stays valid until `release` is called with `owner`, which must
+/// happen exactly once, e.g. through `quicnet_buffer_release`.
+/// `release` is null if there is nothing to release.
+#[repr(C)]
+pub struct QuicnetBuffer {
+    pub data: *const u8,
+    pub len: usize,
+    pub release: QuicnetReleaseFn,
+    pub owner: *mut c_void,
+}
+
+/// Release a buffer received from quicnet, and reset it to an empty buffer.
+///
+/// # Safety
+///
+/// `buffer` must be null or a valid buffer not released before.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_buffer_release(buffer: *mut QuicnetBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        std::mem::replace(buffer, QuicnetBuffer::empty()).release();
+    }
+}
+
+impl QuicnetBuffer {
+    pub(crate) fn empty() -> Self {
+        Self {
+            data: ptr::null(),
+            len: 0,
+            release: None,
+            owner: ptr::null_mut(),
+        }
+    }
+
+    /// Hand `bytes` to C, keeping the underlying memory alive until released.
+    pub(crate) fn from_bytes(bytes: Bytes) -> Self {
+        if bytes.is_empty() {
+            return Self::empty();
+        }
+        let owner = Box::new(bytes);
+        Self {
+            data: owner.as_ptr(),
+            len: owner.len(),
+            release: Some(release_bytes),
+            owner: Box::into_raw(owner) as *mut c_void,
+        }
+    }
+
+    /// Wrap a caller-owned buffer, calling its `release` once the bytes are dropped.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point to `len` bytes that stay valid and unmodified until released,
+    /// and `release` must be callable from any thread.
+    pub(crate) unsafe fn into_bytes(self) -> Bytes {
+        if self.data.is_null() || self.len == 0 {
+            self.release();
+            return Bytes::new();
+        }
+        Bytes::from_owner(ForeignBuffer(self))
+    }
+
+    /// # Safety
+    ///
+    /// The buffer must not be used afterwards.
+    unsafe fn release(self) {
+        if let Some(release) = self.release {
+            release(self.owner);
+        }
+    }
+}
+
+/// `release` of buffers created by `QuicnetBuffer::from_bytes`.
+unsafe extern "C" fn release_bytes(owner: *mut c_void) {
+    drop(Box::from_raw(owner as *mut Bytes));
+}
+
+/// A caller-owned buffer kept alive by `Bytes`.
+struct ForeignBuffer(QuicnetBuffer);
+
+/// The caller guarantees the buffer can be read and released from any thread.
+unsafe impl Send for ForeignBuffer {}
+
+impl AsRef<[u8]> for ForeignBuffer {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.0.data, self.0.len) }
+    }
+}
+
+impl Drop for ForeignBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::replace(&mut self.0, QuicnetBuffer::empty());
+        unsafe { buffer.release() }
+    }
+}
+
+#[cfg(test)]
+mod buffer_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn count_release(owner: *mut c_void) {
+        let released = &*(owner as *const AtomicUsize);
+        released.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_buffer_round_trip() {
+        let data = b"hello";
+        let foreign = QuicnetBuffer {
+            data: data.as_ptr(),
+            len: data.len(),
+            release: Some(count_release),
+            owner: &RELEASED as *const AtomicUsize as *mut c_void,
+        };
+        let bytes = unsafe { foreign.into_bytes() };
+        // no copy is made
+        assert_eq!(bytes.as_ptr(), data.as_ptr());
+        let mut buffer = QuicnetBuffer::from_bytes(bytes.slice(1..));
+        assert_eq!(buffer.data, unsafe { data.as_ptr().add(1) });
+        assert_eq!(buffer.len, 4);
+        drop(bytes);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+        unsafe { quicnet_buffer_release(&mut buffer) };
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+        assert!(buffer.data.is_null());
+        // releasing twice is a no-op after reset
+        unsafe { quicnet_buffer_release(&mut buffer) };
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+    }
+}
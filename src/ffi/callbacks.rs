@@ -0,0 +1,160 @@
+use super::{buffer::QuicnetBuffer, c_message, c_name, QuicnetStatus};
+use crate::server::{EventHandler, ServerEvent};
+use std::{
+    ffi::{c_char, c_int, c_void},
+    ptr,
+    sync::mpsc,
+    thread::JoinHandle,
+};
+
+/// Called with a message or datagram from `peer`. `peer` is only valid
+/// during the call, while the callee owns `payload` and must release it.
+pub type QuicnetMessageCallback = Option<
+    unsafe extern "C" fn(user_data: *mut c_void, peer: *const c_char, payload: QuicnetBuffer),
+>;
+
+/// Called when `peer` connects or disconnects.
+/// `peer` is only valid during the call.
+pub type QuicnetPeerCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, peer: *const c_char)>;
+
+/// Called on a failure not reported to any caller. `peer` is null when
+/// the peer is not identified yet. Both strings are only valid during the call.
+pub type QuicnetErrorCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        peer: *const c_char,
+        status: c_int,
+        message: *const c_char,
+    ),
+>;
+
+/// Callbacks receiving server events, any of them may be null.
+///
+/// By default callbacks are invoked from the server runtime threads,
+/// possibly concurrently, so they and `user_data` must be thread-safe.
+/// With `serialized` set, they are invoked one at a time, in order,
+/// from a single dispatcher thread owned by the server.
+///
+/// Callbacks must not block for long, and must not call
+/// `quicnet_server_free` on their own server. Blocking API calls
+/// are only allowed from the dispatcher thread.
+/// No callback is invoked after `quicnet_server_free` returns.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct QuicnetCallbacks {
+    pub user_data: *mut c_void,
+    pub on_message: QuicnetMessageCallback,
+    pub on_datagram: QuicnetMessageCallback,
+    pub on_peer_connected: QuicnetPeerCallback,
+    pub on_peer_disconnected: QuicnetPeerCallback,
+    pub on_error: QuicnetErrorCallback,
+    pub serialized: bool,
+}
+
+/// Thread safety of `user_data` is up to the caller, see `QuicnetCallbacks`.
+unsafe impl Send for QuicnetCallbacks {}
+unsafe impl Sync for QuicnetCallbacks {}
+
+impl QuicnetCallbacks {
+    /// No callbacks, events are dropped.
+    pub(crate) fn none() -> Self {
+        Self {
+            user_data: ptr::null_mut(),
+            on_message: None,
+            on_datagram: None,
+            on_peer_connected: None,
+            on_peer_disconnected: None,
+            on_error: None,
+            serialized: false,
+        }
+    }
+
+    /// Invoke the callback matching `event`.
+    fn dispatch(&self, event: ServerEvent) {
+        match event {
+            ServerEvent::Message { peer, payload } => {
+                if let Some(f) = self.on_message {
+                    let peer = c_name(&peer);
+                    let payload = QuicnetBuffer::from_bytes(payload);
+                    unsafe { f(self.user_data, peer.as_ptr(), payload) }
+                }
+            }
+            ServerEvent::Datagram { peer, payload } => {
+                if let Some(f) = self.on_datagram {
+                    let peer = c_name(&peer);
+                    let payload = QuicnetBuffer::from_bytes(payload);
+                    unsafe { f(self.user_data, peer.as_ptr(), payload) }
+                }
+            }
+            ServerEvent::Request { peer, .. } => {
+                // the responder is dropped, resetting the request stream
+                tracing::debug!("dropping request from {}", AsRef::<str>::as_ref(&peer));
+            }
+            ServerEvent::PeerConnected { peer, .. } => {
+                if let Some(f) = self.on_peer_connected {
+                    unsafe { f(self.user_data, c_name(&peer).as_ptr()) }
+                }
+            }
+            ServerEvent::PeerDisconnected { peer, .. } => {
+                if let Some(f) = self.on_peer_disconnected {
+                    unsafe { f(self.user_data, c_name(&peer).as_ptr()) }
+                }
+            }
+            ServerEvent::Error { error } => {
+                if let Some(f) = self.on_error {
+                    let peer = error.peer().map(c_name);
+                    let message = c_message(&error);
+                    unsafe {
+                        f(
+                            self.user_data,
+                            peer.as_ref().map_or(ptr::null(), |p| p.as_ptr()),
+                            QuicnetStatus::from(&error) as c_int,
+                            message.as_ptr(),
+                        )
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Delivers events to C callbacks, inline or through a dispatcher thread.
+pub(crate) enum CallbackHandler {
+    Inline(QuicnetCallbacks),
+    Serialized(mpsc::Sender<ServerEvent>),
+}
+
+impl CallbackHandler {
+    /// Create the handler, and the dispatcher thread if delivery is serialized.
+    ///
+    /// The dispatcher exits once the handler is dropped and all queued events are delivered.
+    pub(crate) fn new(
+        callbacks: QuicnetCallbacks,
+    ) -> std::io::Result<(Self, Option<JoinHandle<()>>)> {
+        if !callbacks.serialized {
+            return Ok((CallbackHandler::Inline(callbacks), None));
+        }
+        let (sender, receiver) = mpsc::channel::<ServerEvent>();
+        let dispatcher = std::thread::Builder::new()
+            .name("quicnet-dispatch".to_string())
+            .spawn(move || {
+                for event in receiver {
+                    callbacks.dispatch(event);
+                }
+            })?;
+        Ok((CallbackHandler::Serialized(sender), Some(dispatcher)))
+    }
+}
+
+impl EventHandler for CallbackHandler {
+    fn on_event(&self, event: ServerEvent) {
+        match self {
+            CallbackHandler::Inline(callbacks) => callbacks.dispatch(event),
+            CallbackHandler::Serialized(sender) => {
+                // the dispatcher only exits after the handler is dropped
+                let _ = sender.send(event);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! C API of the staticlib.
+//!
+//! The header `include/quicnet.h` is generated from this module by `build.rs`.
+//! All functions return a `QuicnetStatus` code as `int`, and never unwind into C.
+mod buffer;
+mod callbacks;
+mod queue;
+mod server;
+mod stats;
+mod whitelist;
+
+use crate::error::QuicnetError;
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    panic::AssertUnwindSafe,
+};
+use webpki::DnsName;
+
+/// Status codes returned by the C API.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuicnetStatus {
+    Ok = 0,
+    /// A pointer argument is null, or a string argument is invalid.
+    InvalidArgument = 1,
+    /// Failed to start the server, for reasons other than the codes below.
+    InitFailed = 2,
+    /// The server has already stopped.
+    Stopped = 3,
+    /// Failed to connect to a peer.
+    ConnectFailed = 4,
+    /// No connection to the peer.
+    UnknownPeer = 5,
+    /// Failed to send to a peer.
+    SendFailed = 6,
+    /// The payload exceeds the maximum size.
+    TooLarge = 7,
+    /// A panic was caught at the FFI boundary.
+    Panic = 8,
+    /// No event is pending.
+    NoEvent = 9,
+    /// The config file could not be read or parsed.
+    ConfigParse = 10,
+    /// A certificate file could not be read or parsed.
+    CertificateLoad = 11,
+    /// The private key file could not be read or parsed.
+    KeyLoad = 12,
+    /// The TLS configuration is invalid, e.g. an unparsable CA certificate or private key.
+    TlsBuild = 13,
+    /// The UDP socket could not be bound.
+    Bind = 14,
+    /// The certificate of a peer was rejected.
+    PeerVerification = 15,
+    /// The runtime or one of its threads could not be started.
+    Runtime = 16,
+    /// A config value is out of range.
+    InvalidConfig = 17,
+    /// A certificate revocation list could not be read, parsed or verified.
+    CrlLoad = 18,
+}
+
+impl From<&QuicnetError> for QuicnetStatus {
+    fn from(e: &QuicnetError) -> Self {
+        match e {
+            QuicnetError::ConfigParse { .. } => QuicnetStatus::ConfigParse,
+            QuicnetError::InvalidConfig { .. } => QuicnetStatus::InvalidConfig,
+            QuicnetError::CertificateLoad { .. } => QuicnetStatus::CertificateLoad,
+            QuicnetError::KeyLoad { .. } => QuicnetStatus::KeyLoad,
+            QuicnetError::CrlLoad { .. } => QuicnetStatus::CrlLoad,
+            QuicnetError::TlsBuild { .. } => QuicnetStatus::TlsBuild,
+            QuicnetError::Bind { .. } => QuicnetStatus::Bind,
+            QuicnetError::Connect { .. } => QuicnetStatus::ConnectFailed,
+            QuicnetError::PeerVerification { .. } => QuicnetStatus::PeerVerification,
+            QuicnetError::FrameTooLarge { .. } => QuicnetStatus::TooLarge,
+            QuicnetError::Runtime(_) => QuicnetStatus::Runtime,
+        }
+    }
+}
+
+/// Run the body of an FFI function, converting panics to `QuicnetStatus::Panic`.
+fn ffi_call<F>(f: F) -> c_int
+where
+    F: FnOnce() -> Result<(), QuicnetStatus>,
+{
+    let status = match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => QuicnetStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => QuicnetStatus::Panic,
+    };
+    status as c_int
+}
+
+/// Borrow a nul-terminated UTF-8 string.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a nul-terminated string valid for `'a`.
+unsafe fn c_str<'a>(ptr: *const c_char) -> Result<&'a str, QuicnetStatus> {
+    if ptr.is_null() {
+        return Err(QuicnetStatus::InvalidArgument);
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| QuicnetStatus::InvalidArgument)
+}
+
+/// Parse a nul-terminated domain name.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a nul-terminated string.
+unsafe fn c_dns_name(ptr: *const c_char) -> Result<DnsName, QuicnetStatus> {
+    let name = c_str(ptr)?;
+    webpki::DnsNameRef::try_from_ascii_str(name)
+        .map(DnsName::from)
+        .map_err(|_| QuicnetStatus::InvalidArgument)
+}
+
+/// Borrow a byte buffer, `len` may be zero if `data` is null.
+///
+/// # Safety
+///
+/// `data` must be null or point to `len` readable bytes valid for `'a`.
+unsafe fn c_bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], QuicnetStatus> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(QuicnetStatus::InvalidArgument),
+        (false, len) => Ok(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Domain names never contain nul bytes.
+fn c_name(peer: &DnsName) -> CString {
+    CString::new(AsRef::<str>::as_ref(peer)).expect("domain name contains nul byte")
+}
+
+/// Error messages as C strings, with nul bytes removed.
+fn c_message(error: &QuicnetError) -> CString {
+    CString::new(error.to_string().replace('\0', "")).expect("nul bytes removed")
+}
@@ -0,0 +1,347 @@
+use super::{
+    buffer::QuicnetBuffer, c_message, c_name, ffi_call, server::QuicnetServer, QuicnetStatus,
+};
+use crate::server::{EventHandler, ServerEvent};
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    ffi::{c_char, c_int, CString},
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    ptr,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// Kind of a `QuicnetEvent`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuicnetEventKind {
+    Message = 0,
+    Datagram = 1,
+    PeerConnected = 2,
+    PeerDisconnected = 3,
+    Error = 4,
+}
+
+/// An event taken from the queue.
+///
+/// `peer` and `message` stay valid until the next `quicnet_poll_event` or
+/// `quicnet_next_event` call on the same server, or until it is freed.
+/// The caller owns `payload` and must release it.
+#[repr(C)]
+pub struct QuicnetEvent {
+    pub kind: QuicnetEventKind,
+    /// Nul-terminated peer name, null for errors of unidentified peers.
+    pub peer: *const c_char,
+    /// Payload of messages and datagrams, empty otherwise.
+    pub payload: QuicnetBuffer,
+    /// `QuicnetStatus` of errors, 0 otherwise.
+    pub status: c_int,
+    /// Nul-terminated message of errors, null otherwise.
+    pub message: *const c_char,
+}
+
+/// Take the next queued event without blocking.
+///
+/// Returns `QUICNET_STATUS_NO_EVENT` if none is pending, or
+/// `QUICNET_STATUS_STOPPED` once the server has stopped and all events are taken.
+///
+/// # Safety
+///
+/// `server` must be a live handle from `quicnet_server_init_with_queue`,
+/// `out` must be writable. Events must be taken by one thread at a time.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_poll_event(
+    server: *const QuicnetServer,
+    out: *mut QuicnetEvent,
+) -> c_int {
+    ffi_call(|| {
+        let (events, out) = event_args(server, out)?;
+        events.next(Some(Duration::ZERO), out)
+    })
+}
+
+/// Take the next queued event, waiting at most `timeout_ms` for one
+/// (forever if negative). Returns like `quicnet_poll_event`.
+///
+/// # Safety
+///
+/// `server` must be a live handle from `quicnet_server_init_with_queue`,
+/// `out` must be writable. Events must be taken by one thread at a time.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_next_event(
+    server: *const QuicnetServer,
+    timeout_ms: i64,
+    out: *mut QuicnetEvent,
+) -> c_int {
+    ffi_call(|| {
+        let (events, out) = event_args(server, out)?;
+        let timeout = u64::try_from(timeout_ms).ok().map(Duration::from_millis);
+        events.next(timeout, out)
+    })
+}
+
+/// The eventfd of the queue, readable while events are pending or once
+/// the server has stopped. Returns -1 without a queue, or on platforms
+/// without eventfd.
+///
+/// The fd is owned by the server and closed by `quicnet_server_free`.
+/// It must only be polled for readability, not read.
+///
+/// # Safety
+///
+/// `server` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_event_fd(server: *const QuicnetServer) -> c_int {
+    server
+        .as_ref()
+        .and_then(|s| s.events.as_ref())
+        .map_or(-1, |events| events.event_fd())
+}
+
+/// Number of events dropped because the queue was full, 0 without a queue.
+///
+/// # Safety
+///
+/// `server` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_dropped_events(server: *const QuicnetServer) -> u64 {
+    server
+        .as_ref()
+        .and_then(|s| s.events.as_ref())
+        .map_or(0, |events| events.dropped())
+}
+
+/// Check the arguments of the event functions.
+///
+/// # Safety
+///
+/// `server` must be null or a live handle, `out` null or writable.
+unsafe fn event_args<'a>(
+    server: *const QuicnetServer,
+    out: *mut QuicnetEvent,
+) -> Result<(&'a EventQueue, &'a mut QuicnetEvent), QuicnetStatus> {
+    let events = server
+        .as_ref()
+        .and_then(|s| s.events.as_deref())
+        .ok_or(QuicnetStatus::InvalidArgument)?;
+    let out = out.as_mut().ok_or(QuicnetStatus::InvalidArgument)?;
+    Ok((events, out))
+}
+
+/// Bounded queue of events, consumed by a C event loop.
+///
+/// Messages, datagrams and errors are dropped once `capacity` events are
+/// queued. Peer connection events are always queued, so the application
+/// never misses a disconnection. There is at most one of each per
+/// connection, which keeps the queue bounded.
+///
+/// The eventfd is readable exactly while events are pending,
+/// or once the server has stopped.
+pub(crate) struct EventQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+    capacity: usize,
+    event_fd: Option<OwnedFd>,
+}
+
+struct QueueState {
+    events: VecDeque<ServerEvent>,
+    /// Set once the server thread has exited.
+    closed: bool,
+    /// Number of events dropped because the queue was full.
+    dropped: u64,
+    /// Owner of the strings referenced by the last returned `QuicnetEvent`.
+    current: Option<CurrentEvent>,
+}
+
+struct CurrentEvent {
+    _peer: Option<CString>,
+    _message: Option<CString>,
+}
+
+impl EventQueue {
+    pub(crate) fn new(capacity: usize) -> std::io::Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            state: Mutex::new(QueueState {
+                events: VecDeque::with_capacity(capacity),
+                closed: false,
+                dropped: 0,
+                current: None,
+            }),
+            ready: Condvar::new(),
+            capacity,
+            event_fd: make_event_fd()?,
+        }))
+    }
+
+    /// The eventfd, or -1 where eventfd is not supported.
+    pub(crate) fn event_fd(&self) -> RawFd {
+        self.event_fd.as_ref().map_or(-1, |fd| fd.as_raw_fd())
+    }
+
+    /// Take the next event, waiting at most `timeout` (forever if `None`).
+    pub(crate) fn next(
+        &self,
+        timeout: Option<Duration>,
+        out: &mut QuicnetEvent,
+    ) -> Result<(), QuicnetStatus> {
+        let state = self.lock();
+        let mut state = match timeout {
+            Some(timeout) => {
+                self.ready
+                    .wait_timeout_while(state, timeout, |s| s.events.is_empty() && !s.closed)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            }
+            None => self
+                .ready
+                .wait_while(state, |s| s.events.is_empty() && !s.closed)
+                .unwrap_or_else(|e| e.into_inner()),
+        };
+        // the previous event is released on every call
+        drop(state.current.take());
+        let Some(event) = state.events.pop_front() else {
+            return Err(match state.closed {
+                true => QuicnetStatus::Stopped,
+                false => QuicnetStatus::NoEvent,
+            });
+        };
+        if state.events.is_empty() && !state.closed {
+            self.clear_fd();
+        }
+        state.current = Some(fill_event(event, out));
+        Ok(())
+    }
+
+    /// Number of events dropped because the queue was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.lock().dropped
+    }
+
+    fn push(&self, event: ServerEvent) {
+        let mut state = self.lock();
+        let lifecycle = matches!(
+            event,
+            ServerEvent::PeerConnected { .. } | ServerEvent::PeerDisconnected { .. }
+        );
+        if state.events.len() >= self.capacity && !lifecycle {
+            tracing::warn!("event queue full, dropping event {event:?}");
+            state.dropped += 1;
+            return;
+        }
+        if state.events.is_empty() {
+            self.signal_fd();
+        }
+        state.events.push_back(event);
+        self.ready.notify_one();
+    }
+
+    /// Mark the queue as closed, once no more events can be pushed.
+    pub(crate) fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        if state.events.is_empty() {
+            self.signal_fd();
+        }
+        self.ready.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Make the eventfd readable, called with the state locked.
+    fn signal_fd(&self) {
+        if let Some(fd) = &self.event_fd {
+            let one = 1u64;
+            // only fails if the counter overflows, which it never does here
+            unsafe { libc::write(fd.as_raw_fd(), &one as *const u64 as *const _, 8) };
+        }
+    }
+
+    /// Reset the eventfd counter, called with the state locked.
+    fn clear_fd(&self) {
+        if let Some(fd) = &self.event_fd {
+            let mut count = 0u64;
+            // nonblocking, fails with EAGAIN if the counter is already zero
+            unsafe { libc::read(fd.as_raw_fd(), &mut count as *mut u64 as *mut _, 8) };
+        }
+    }
+}
+
+/// Feeds server events into an `EventQueue`.
+pub(crate) struct QueueHandler(pub(crate) Arc<EventQueue>);
+
+impl EventHandler for QueueHandler {
+    fn on_event(&self, event: ServerEvent) {
+        match event {
+            ServerEvent::Request { peer, .. } => {
+                // the responder is dropped, resetting the request stream
+                tracing::debug!("dropping request from {}", AsRef::<str>::as_ref(&peer));
+            }
+            event => self.0.push(event),
+        }
+    }
+}
+
+/// Write `event` to `out`, returning the owner of the memory it references.
+fn fill_event(event: ServerEvent, out: &mut QuicnetEvent) -> CurrentEvent {
+    let (kind, peer, payload, status, message) = match event {
+        ServerEvent::Message { peer, payload } => {
+            (QuicnetEventKind::Message, Some(peer), payload, 0, None)
+        }
+        ServerEvent::Datagram { peer, payload } => {
+            (QuicnetEventKind::Datagram, Some(peer), payload, 0, None)
+        }
+        ServerEvent::PeerConnected { peer, .. } => (
+            QuicnetEventKind::PeerConnected,
+            Some(peer),
+            Bytes::new(),
+            0,
+            None,
+        ),
+        ServerEvent::PeerDisconnected { peer, .. } => (
+            QuicnetEventKind::PeerDisconnected,
+            Some(peer),
+            Bytes::new(),
+            0,
+            None,
+        ),
+        ServerEvent::Error { error } => (
+            QuicnetEventKind::Error,
+            error.peer().cloned(),
+            Bytes::new(),
+            error.code(),
+            Some(c_message(&error)),
+        ),
+        ServerEvent::Request { .. } => unreachable!("requests are not queued"),
+    };
+    let peer = peer.as_ref().map(c_name);
+    *out = QuicnetEvent {
+        kind,
+        peer: peer.as_ref().map_or(ptr::null(), |p| p.as_ptr()),
+        payload: QuicnetBuffer::from_bytes(payload),
+        status,
+        message: message.as_ref().map_or(ptr::null(), |m| m.as_ptr()),
+    };
+    CurrentEvent {
+        _peer: peer,
+        _message: message,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn make_event_fd() -> std::io::Result<Option<OwnedFd>> {
+    use std::os::fd::FromRawFd;
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn make_event_fd() -> std::io::Result<Option<OwnedFd>> {
+    Ok(None)
+}
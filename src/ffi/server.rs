@@ -0,0 +1,865 @@
+use super::{
+    buffer::QuicnetBuffer,
+    c_bytes, c_dns_name, c_str,
+    callbacks::{CallbackHandler, QuicnetCallbacks},
+    ffi_call,
+    queue::{EventQueue, QueueHandler},
+    QuicnetStatus,
+};
+use crate::{
+    config::ServerConfig,
+    server::{DatagramError, EventHandler, SendError, Server, ServerCommand},
+};
+use bytes::Bytes;
+use std::{
+    ffi::{c_char, c_int, c_void},
+    net::SocketAddr,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    thread::JoinHandle,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// Opaque handle to a running server.
+pub struct QuicnetServer {
+    pub(super) server: Server,
+    /// Thread delivering serialized callbacks.
+    dispatcher: Option<JoinHandle<()>>,
+    /// Queue of events, if polled by the application.
+    pub(super) events: Option<Arc<EventQueue>>,
+}
+
+/// Load the config file at `config_path` and start a server with `n_threads`
+/// runtime threads (0 for one per CPU). The handle is written to `out`.
+///
+/// Events are delivered to `callbacks`, which is copied and may be null
+/// to ignore all events.
+///
+/// # Safety
+///
+/// `config_path` must be a nul-terminated string, `out` must be writable,
+/// `callbacks` must be null or valid, and its `user_data` must outlive the server.
+/// The handle must be released with `quicnet_server_free`.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_init_from_file(
+    config_path: *const c_char,
+    n_threads: usize,
+    callbacks: *const QuicnetCallbacks,
+    out: *mut *mut QuicnetServer,
+) -> c_int {
+    ffi_call(|| {
+        if out.is_null() {
+            return Err(QuicnetStatus::InvalidArgument);
+        }
+        let callbacks = callbacks
+            .as_ref()
+            .copied()
+            .unwrap_or_else(QuicnetCallbacks::none);
+        let (handler, dispatcher) = CallbackHandler::new(callbacks).map_err(|e| {
+            tracing::error!("failed to start dispatcher thread: {e}");
+            QuicnetStatus::InitFailed
+        })?;
+        // on failure, the handler is dropped and the dispatcher exits
+        let server = start(config_path, n_threads, Arc::new(handler))?;
+        *out = Box::into_raw(Box::new(QuicnetServer {
+            server,
+            dispatcher,
+            events: None,
+        }));
+        Ok(())
+    })
+}
+
+/// Like `quicnet_server_init_from_file`, but events are queued for
+/// `quicnet_poll_event` and `quicnet_next_event` instead of invoking callbacks.
+///
+/// Once `queue_capacity` events are queued, further messages, datagrams and
+/// errors are dropped and counted by `quicnet_server_dropped_events`.
+/// Peer connection events are never dropped.
+///
+/// # Safety
+///
+/// `config_path` must be a nul-terminated string, `out` must be writable.
+/// The handle must be released with `quicnet_server_free`.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_init_with_queue(
+    config_path: *const c_char,
+    n_threads: usize,
+    queue_capacity: usize,
+    out: *mut *mut QuicnetServer,
+) -> c_int {
+    ffi_call(|| {
+        if out.is_null() || queue_capacity == 0 {
+            return Err(QuicnetStatus::InvalidArgument);
+        }
+        let events = EventQueue::new(queue_capacity).map_err(|e| {
+            tracing::error!("failed to create eventfd: {e}");
+            QuicnetStatus::InitFailed
+        })?;
+        let server = start(
+            config_path,
+            n_threads,
+            Arc::new(QueueHandler(events.clone())),
+        )?;
+        *out = Box::into_raw(Box::new(QuicnetServer {
+            server,
+            dispatcher: None,
+            events: Some(events),
+        }));
+        Ok(())
+    })
+}
+
+/// Connect to the peer `domain` at `addr` (e.g. `"127.0.0.1:12345"`),
+/// blocking until the connection is established.
+/// The peer is registered under the smallest name of its certificate,
+/// as reported by `on_peer_connected`, which may differ from `domain`.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `addr` and `domain` nul-terminated strings.
+/// Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_connect(
+    server: *const QuicnetServer,
+    addr: *const c_char,
+    domain: *const c_char,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let addr: SocketAddr = c_str(addr)?
+            .parse()
+            .map_err(|_| QuicnetStatus::InvalidArgument)?;
+        let domain = c_dns_name(domain)?;
+        let (reply, result) = oneshot::channel();
+        command(
+            server,
+            ServerCommand::Connect {
+                addr,
+                domain,
+                reply,
+            },
+        )?;
+        match result.blocking_recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(QuicnetStatus::from(&e)),
+            Err(_) => Err(QuicnetStatus::Stopped),
+        }
+    })
+}
+
+/// Re-read the certificates and private key for new connections, keeping
+/// existing connections. Nothing is changed if the files are invalid.
+///
+/// # Safety
+///
+/// `server` must be a live handle. Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_reload_tls(server: *const QuicnetServer) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let (reply, result) = oneshot::channel();
+        command(server, ServerCommand::ReloadTls { reply })?;
+        match result.blocking_recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(QuicnetStatus::from(&e)),
+            Err(_) => Err(QuicnetStatus::Stopped),
+        }
+    })
+}
+
+/// Send `len` bytes at `data` to `peer` as a message,
+/// blocking until the message is written. The data is copied.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `peer` a nul-terminated string,
+/// `data` must point to `len` readable bytes. Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_send(
+    server: *const QuicnetServer,
+    peer: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let peer = c_dns_name(peer)?;
+        let payload = Bytes::copy_from_slice(c_bytes(data, len)?);
+        let (reply, result) = oneshot::channel();
+        command(
+            server,
+            ServerCommand::Send {
+                peer,
+                payload,
+                reply,
+            },
+        )?;
+        match result.blocking_recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(send_status(&e)),
+            Err(_) => Err(QuicnetStatus::Stopped),
+        }
+    })
+}
+
+/// Called once with the `QuicnetStatus` of an asynchronous send.
+pub type QuicnetSendCallback = Option<unsafe extern "C" fn(user_data: *mut c_void, status: c_int)>;
+
+/// Send `payload` to `peer` as a message without copying it, and return
+/// without waiting for the message to be written.
+///
+/// `payload` is always released, once quicnet no longer references it.
+/// If `QUICNET_STATUS_OK` is returned, `on_complete` (if not null) is called
+/// exactly once with the result, from a server runtime thread or possibly
+/// before this function returns if the server is stopping.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `peer` a nul-terminated string. `payload` must
+/// stay valid and unmodified until released, and its `release` and `on_complete`
+/// must be callable from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_send_buffer(
+    server: *const QuicnetServer,
+    peer: *const c_char,
+    payload: QuicnetBuffer,
+    on_complete: QuicnetSendCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    // take ownership first, so that the buffer is released on every path
+    let payload = payload.into_bytes();
+    ffi_call(move || {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let peer = c_dns_name(peer)?;
+        let (reply, result) = oneshot::channel();
+        command(
+            server,
+            ServerCommand::Send {
+                peer,
+                payload,
+                reply,
+            },
+        )?;
+        let completion = Completion {
+            callback: on_complete,
+            user_data,
+        };
+        server.server.runtime().spawn(async move {
+            let status = match result.await {
+                Ok(Ok(())) => QuicnetStatus::Ok,
+                Ok(Err(e)) => send_status(&e),
+                Err(_) => QuicnetStatus::Stopped,
+            };
+            completion.complete(status);
+        });
+        Ok(())
+    })
+}
+
+/// Send `len` bytes at `data` to `peer` as an unreliable datagram. The data is copied.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `peer` a nul-terminated string,
+/// `data` must point to `len` readable bytes. Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_send_datagram(
+    server: *const QuicnetServer,
+    peer: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let peer = c_dns_name(peer)?;
+        let payload = Bytes::copy_from_slice(c_bytes(data, len)?);
+        let (reply, result) = oneshot::channel();
+        command(
+            server,
+            ServerCommand::SendDatagram {
+                peer,
+                payload,
+                reply,
+            },
+        )?;
+        match result.blocking_recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(DatagramError::UnknownPeer(_))) => Err(QuicnetStatus::UnknownPeer),
+            Ok(Err(DatagramError::TooLarge { .. })) => Err(QuicnetStatus::TooLarge),
+            Ok(Err(_)) => Err(QuicnetStatus::SendFailed),
+            Err(_) => Err(QuicnetStatus::Stopped),
+        }
+    })
+}
+
+/// Number of connected peers, 0 if `server` is null.
+///
+/// # Safety
+///
+/// `server` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_peer_count(server: *const QuicnetServer) -> usize {
+    server.as_ref().map_or(0, |s| s.server.peers().len())
+}
+
+/// Stop accepting connections, wait at most `drain_timeout_ms` for in-flight
+/// streams and sent messages to be read, close all connections, and block
+/// until the server thread exits.
+///
+/// # Safety
+///
+/// `server` must be a live handle. Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_shutdown(
+    server: *const QuicnetServer,
+    drain_timeout_ms: u64,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let drain_timeout = Duration::from_millis(drain_timeout_ms);
+        // the server may already be stopped, join anyway
+        let _ = command(server, ServerCommand::Shutdown { drain_timeout });
+        join(server)
+    })
+}
+
+/// Close all connections immediately, and block until the server thread exits.
+///
+/// # Safety
+///
+/// `server` must be a live handle. Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_abort(server: *const QuicnetServer) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        // the server may already be stopped, join anyway
+        let _ = command(server, ServerCommand::Abort);
+        join(server)
+    })
+}
+
+/// Release a server handle, shutting the server down if still running,
+/// and waiting for serialized callbacks of queued events to return.
+///
+/// For a server with an event queue, events not taken yet are discarded,
+/// the strings of the last taken event are released and the eventfd is
+/// closed. Payloads already taken remain owned by the caller.
+///
+/// # Safety
+///
+/// `server` must be null or a handle from `quicnet_server_init_from_file`
+/// or `quicnet_server_init_with_queue` that is not used afterwards.
+/// Must not be called from a callback, a server runtime thread, or while
+/// another thread is waiting in `quicnet_next_event`.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_free(server: *mut QuicnetServer) {
+    if !server.is_null() {
+        let server = Box::from_raw(server);
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(move || {
+            let QuicnetServer {
+                server,
+                dispatcher,
+                events,
+            } = *server;
+            // dropping the server drops the handler, which stops the dispatcher
+            drop(server);
+            if let Some(dispatcher) = dispatcher {
+                let _ = dispatcher.join();
+            }
+            // closes the eventfd
+            drop(events);
+        }));
+    }
+}
+
+/// Load the config and start the server.
+///
+/// # Safety
+///
+/// `config_path` must be a nul-terminated string.
+unsafe fn start(
+    config_path: *const c_char,
+    n_threads: usize,
+    handler: Arc<dyn EventHandler>,
+) -> Result<Server, QuicnetStatus> {
+    let config_path = c_str(config_path)?;
+    ServerConfig::load(config_path)
+        .and_then(|config| Server::init(n_threads, config, handler))
+        .map_err(|e| {
+            tracing::error!("failed to start server: {e}");
+            QuicnetStatus::from(&e)
+        })
+}
+
+pub(super) fn command(server: &QuicnetServer, cmd: ServerCommand) -> Result<(), QuicnetStatus> {
+    server
+        .server
+        .command(cmd)
+        .map_err(|_| QuicnetStatus::Stopped)
+}
+
+/// Wait for the server thread to exit, after which no more events are produced.
+fn join(server: &QuicnetServer) -> Result<(), QuicnetStatus> {
+    let result = server.server.join().map_err(|e| {
+        tracing::error!("server thread failed: {e}");
+        QuicnetStatus::Panic
+    });
+    if let Some(events) = &server.events {
+        events.close();
+    }
+    result
+}
+
+/// Invokes a `QuicnetSendCallback` exactly once, with `Stopped` if dropped.
+struct Completion {
+    callback: QuicnetSendCallback,
+    user_data: *mut c_void,
+}
+
+/// The caller guarantees the callback can be invoked from any thread.
+unsafe impl Send for Completion {}
+
+impl Completion {
+    fn complete(mut self, status: QuicnetStatus) {
+        if let Some(callback) = self.callback.take() {
+            unsafe { callback(self.user_data, status as c_int) }
+        }
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if let Some(callback) = self.callback.take() {
+            unsafe { callback(self.user_data, QuicnetStatus::Stopped as c_int) }
+        }
+    }
+}
+
+fn send_status(e: &SendError) -> QuicnetStatus {
+    match e {
+        SendError::UnknownPeer(_) => QuicnetStatus::UnknownPeer,
+        SendError::Frame(crate::server::FrameError::TooLarge { .. }) => QuicnetStatus::TooLarge,
+        _ => QuicnetStatus::SendFailed,
+    }
+}
+
+#[cfg(test)]
+mod ffi_tests {
+    use super::*;
+    use crate::ffi::buffer::quicnet_buffer_release;
+    use crate::ffi::queue::{
+        quicnet_next_event, quicnet_poll_event, quicnet_server_dropped_events,
+        quicnet_server_event_fd, EventQueue, QueueHandler, QuicnetEvent, QuicnetEventKind,
+    };
+    use crate::ffi::stats::{
+        quicnet_server_endpoint_stats, quicnet_server_metrics, quicnet_server_peer_stats,
+        QuicnetCongestionControl, QuicnetEndpointStats, QuicnetPeerStats,
+    };
+    use crate::ffi::whitelist::{quicnet_server_whitelist_add, quicnet_server_whitelist_replace};
+    use crate::server::{EventHandler, ServerEvent};
+    use bytes::Bytes;
+    use std::{
+        ffi::{c_void, CStr, CString},
+        ptr,
+        sync::mpsc,
+    };
+
+    const CONFIG_A: &str = "data/config-ddpwuxrmp.toml";
+    const CONFIG_B: &str = "data/config-rehdhssj.toml";
+
+    #[test]
+    fn test_init_invalid() {
+        let mut server = ptr::null_mut();
+        let missing = CString::new("data/missing.toml").unwrap();
+        unsafe {
+            assert_eq!(
+                quicnet_server_init_from_file(ptr::null(), 1, ptr::null(), &mut server),
+                QuicnetStatus::InvalidArgument as c_int
+            );
+            assert_eq!(
+                quicnet_server_init_from_file(missing.as_ptr(), 1, ptr::null(), &mut server),
+                QuicnetStatus::ConfigParse as c_int
+            );
+            assert!(server.is_null());
+            quicnet_server_free(server);
+        }
+    }
+
+    #[test]
+    fn test_connect_send() {
+        let server_a = init_server(CONFIG_A, "ffi-a.toml", None);
+        let server_b = init_server(CONFIG_B, "ffi-b.toml", None);
+        let addr_b = unsafe { (*server_b).server.local_addr().unwrap() };
+        let addr_b = CString::new(addr_b.to_string()).unwrap();
+        let name_b = CString::new("rehdhssj.cn").unwrap();
+        let payload = b"hello";
+        unsafe {
+            assert_eq!(
+                quicnet_server_send(server_a, name_b.as_ptr(), payload.as_ptr(), payload.len()),
+                QuicnetStatus::UnknownPeer as c_int
+            );
+            let mut stats = std::mem::MaybeUninit::<QuicnetPeerStats>::uninit();
+            assert_eq!(
+                quicnet_server_peer_stats(server_a, name_b.as_ptr(), stats.as_mut_ptr()),
+                QuicnetStatus::UnknownPeer as c_int
+            );
+            assert_eq!(
+                quicnet_server_connect(server_a, addr_b.as_ptr(), name_b.as_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(quicnet_server_peer_count(server_a), 1);
+            assert_eq!(
+                quicnet_server_reload_tls(server_a),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(
+                quicnet_server_whitelist_add(server_a, name_b.as_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(
+                quicnet_server_whitelist_replace(server_a, ptr::null(), 1, false),
+                QuicnetStatus::InvalidArgument as c_int
+            );
+            assert_eq!(
+                quicnet_server_send(server_a, name_b.as_ptr(), payload.as_ptr(), payload.len()),
+                QuicnetStatus::Ok as c_int
+            );
+            let mut stats = std::mem::MaybeUninit::<QuicnetPeerStats>::uninit();
+            assert_eq!(
+                quicnet_server_peer_stats(server_a, name_b.as_ptr(), stats.as_mut_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            let stats = stats.assume_init();
+            assert_eq!(stats.congestion_control, QuicnetCongestionControl::Cubic);
+            assert!(stats.sent_bytes > 0);
+            let mut stats = std::mem::MaybeUninit::<QuicnetEndpointStats>::uninit();
+            assert_eq!(
+                quicnet_server_endpoint_stats(server_a, stats.as_mut_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(stats.assume_init().peers, 1);
+            let mut metrics = QuicnetBuffer::empty();
+            assert_eq!(
+                quicnet_server_metrics(server_a, &mut metrics),
+                QuicnetStatus::Ok as c_int
+            );
+            let text = std::slice::from_raw_parts(metrics.data, metrics.len);
+            let text = std::str::from_utf8(text).unwrap();
+            assert!(text.contains("quicnet_messages_sent_total 1\n"));
+            quicnet_buffer_release(&mut metrics);
+            assert_eq!(
+                quicnet_server_shutdown(server_a, 100),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(
+                quicnet_server_send(server_a, name_b.as_ptr(), payload.as_ptr(), payload.len()),
+                QuicnetStatus::Stopped as c_int
+            );
+            assert_eq!(quicnet_server_abort(server_b), QuicnetStatus::Ok as c_int);
+            quicnet_server_free(server_a);
+            quicnet_server_free(server_b);
+        }
+    }
+
+    #[test]
+    fn test_serialized_callbacks() {
+        let (sender, events) = mpsc::channel::<String>();
+        let callbacks = QuicnetCallbacks {
+            user_data: &sender as *const _ as *mut c_void,
+            on_message: Some(on_message),
+            on_peer_connected: Some(on_peer_connected),
+            serialized: true,
+            ..QuicnetCallbacks::none()
+        };
+        let server_a = init_server(CONFIG_A, "ffi-callbacks-a.toml", None);
+        let server_b = init_server(CONFIG_B, "ffi-callbacks-b.toml", Some(&callbacks));
+        let addr_b = unsafe { (*server_b).server.local_addr().unwrap() };
+        let addr_b = CString::new(addr_b.to_string()).unwrap();
+        let name_b = CString::new("rehdhssj.cn").unwrap();
+        let payload = b"hello";
+        unsafe {
+            assert_eq!(
+                quicnet_server_connect(server_a, addr_b.as_ptr(), name_b.as_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(
+                quicnet_server_send(server_a, name_b.as_ptr(), payload.as_ptr(), payload.len()),
+                QuicnetStatus::Ok as c_int
+            );
+        }
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            events.recv_timeout(timeout).unwrap(),
+            "connected ddpwuxrmp.uk"
+        );
+        assert_eq!(
+            events.recv_timeout(timeout).unwrap(),
+            "message ddpwuxrmp.uk hello"
+        );
+        unsafe {
+            quicnet_server_free(server_a);
+            quicnet_server_free(server_b);
+        }
+    }
+
+    #[test]
+    fn test_send_buffer() {
+        let server_a = init_server(CONFIG_A, "ffi-buffer-a.toml", None);
+        let server_b = init_server_with_queue(CONFIG_B, "ffi-buffer-b.toml");
+        let addr_b = unsafe { (*server_b).server.local_addr().unwrap() };
+        let addr_b = CString::new(addr_b.to_string()).unwrap();
+        let name_b = CString::new("rehdhssj.cn").unwrap();
+        let data = vec![7u8; 1 << 20];
+        let (sender, results) = mpsc::channel::<c_int>();
+        let user_data = &sender as *const _ as *mut c_void;
+        let buffer = || QuicnetBuffer {
+            data: data.as_ptr(),
+            len: data.len(),
+            release: Some(release_payload),
+            owner: user_data,
+        };
+        let timeout = Duration::from_secs(5);
+        unsafe {
+            // released even if the send is rejected
+            assert_eq!(
+                quicnet_server_send_buffer(
+                    server_a,
+                    ptr::null(),
+                    buffer(),
+                    Some(on_send_complete),
+                    user_data
+                ),
+                QuicnetStatus::InvalidArgument as c_int
+            );
+            assert_eq!(results.recv_timeout(timeout), Ok(-1));
+            assert_eq!(
+                quicnet_server_connect(server_a, addr_b.as_ptr(), name_b.as_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(
+                quicnet_server_send_buffer(
+                    server_a,
+                    name_b.as_ptr(),
+                    buffer(),
+                    Some(on_send_complete),
+                    user_data
+                ),
+                QuicnetStatus::Ok as c_int
+            );
+            let mut event = QuicnetEvent {
+                kind: QuicnetEventKind::Error,
+                peer: ptr::null(),
+                payload: QuicnetBuffer::empty(),
+                status: 0,
+                message: ptr::null(),
+            };
+            loop {
+                assert_eq!(
+                    quicnet_next_event(server_b, 5000, &mut event),
+                    QuicnetStatus::Ok as c_int
+                );
+                if event.kind == QuicnetEventKind::Message {
+                    break;
+                }
+            }
+            let received = &mut event.payload;
+            assert_eq!(
+                std::slice::from_raw_parts(received.data, received.len),
+                data
+            );
+            quicnet_buffer_release(received);
+            quicnet_server_free(server_a);
+            quicnet_server_free(server_b);
+        }
+        // completed, and released once the stream no longer needs the data
+        let mut results = results.try_iter().collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [-1, QuicnetStatus::Ok as c_int]);
+    }
+
+    #[test]
+    fn test_event_queue() {
+        let server_a = init_server(CONFIG_A, "ffi-queue-a.toml", None);
+        let server_b = init_server_with_queue(CONFIG_B, "ffi-queue-b.toml");
+        let addr_b = unsafe { (*server_b).server.local_addr().unwrap() };
+        let addr_b = CString::new(addr_b.to_string()).unwrap();
+        let name_b = CString::new("rehdhssj.cn").unwrap();
+        let payload = b"hello";
+        let mut event = QuicnetEvent {
+            kind: QuicnetEventKind::Error,
+            peer: ptr::null(),
+            payload: QuicnetBuffer::empty(),
+            status: 0,
+            message: ptr::null(),
+        };
+        unsafe {
+            assert_eq!(quicnet_server_event_fd(server_a), -1);
+            let fd = quicnet_server_event_fd(server_b);
+            assert!(fd >= 0);
+            assert!(!readable(fd, 0));
+            assert_eq!(
+                quicnet_poll_event(server_b, &mut event),
+                QuicnetStatus::NoEvent as c_int
+            );
+            assert_eq!(
+                quicnet_server_connect(server_a, addr_b.as_ptr(), name_b.as_ptr()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(
+                quicnet_server_send(server_a, name_b.as_ptr(), payload.as_ptr(), payload.len()),
+                QuicnetStatus::Ok as c_int
+            );
+            assert!(readable(fd, 5000));
+            assert_eq!(
+                quicnet_poll_event(server_b, &mut event),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(event.kind, QuicnetEventKind::PeerConnected);
+            assert_eq!(CStr::from_ptr(event.peer).to_str(), Ok("ddpwuxrmp.uk"));
+            assert_eq!(
+                quicnet_next_event(server_b, 5000, &mut event),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(event.kind, QuicnetEventKind::Message);
+            let buffer = &mut event.payload;
+            assert_eq!(std::slice::from_raw_parts(buffer.data, buffer.len), payload);
+            quicnet_buffer_release(buffer);
+            assert!(!readable(fd, 0));
+            assert_eq!(quicnet_server_abort(server_a), QuicnetStatus::Ok as c_int);
+            assert_eq!(quicnet_server_abort(server_b), QuicnetStatus::Ok as c_int);
+            // remaining events are still delivered after the server stopped
+            assert!(readable(fd, 0));
+            assert_eq!(
+                quicnet_next_event(server_b, -1, &mut event),
+                QuicnetStatus::Ok as c_int
+            );
+            assert_eq!(event.kind, QuicnetEventKind::PeerDisconnected);
+            assert_eq!(
+                quicnet_next_event(server_b, -1, &mut event),
+                QuicnetStatus::Stopped as c_int
+            );
+            assert!(readable(fd, 0));
+            quicnet_server_free(server_a);
+            quicnet_server_free(server_b);
+        }
+    }
+
+    #[test]
+    fn test_event_queue_overflow() {
+        let events = EventQueue::new(1).unwrap();
+        let handler = QueueHandler(events.clone());
+        let peer =
+            webpki::DnsName::from(webpki::DnsNameRef::try_from_ascii_str("ddpwuxrmp.uk").unwrap());
+        let message = || ServerEvent::Message {
+            peer: peer.clone(),
+            payload: Bytes::from("hello"),
+        };
+        handler.on_event(message());
+        handler.on_event(message());
+        // lifecycle events are queued past the capacity
+        handler.on_event(ServerEvent::PeerDisconnected {
+            peer: peer.clone(),
+            reason: quinn::ConnectionError::LocallyClosed,
+        });
+        handler.on_event(message());
+        assert_eq!(events.dropped(), 2);
+        let mut event = QuicnetEvent {
+            kind: QuicnetEventKind::Error,
+            peer: ptr::null(),
+            payload: QuicnetBuffer::empty(),
+            status: 0,
+            message: ptr::null(),
+        };
+        events.next(Some(Duration::ZERO), &mut event).unwrap();
+        assert_eq!(event.kind, QuicnetEventKind::Message);
+        unsafe { quicnet_buffer_release(&mut event.payload) };
+        events.next(Some(Duration::ZERO), &mut event).unwrap();
+        assert_eq!(event.kind, QuicnetEventKind::PeerDisconnected);
+        assert!(matches!(
+            events.next(Some(Duration::ZERO), &mut event),
+            Err(QuicnetStatus::NoEvent)
+        ));
+        assert_eq!(unsafe { quicnet_server_dropped_events(ptr::null()) }, 0);
+    }
+
+    // helper functions
+
+    unsafe extern "C" fn on_message(
+        user_data: *mut c_void,
+        peer: *const c_char,
+        mut payload: QuicnetBuffer,
+    ) {
+        let sender = &*(user_data as *const mpsc::Sender<String>);
+        let peer = CStr::from_ptr(peer).to_str().unwrap();
+        let data = std::slice::from_raw_parts(payload.data, payload.len);
+        let _ = sender.send(format!(
+            "message {peer} {}",
+            std::str::from_utf8(data).unwrap()
+        ));
+        quicnet_buffer_release(&mut payload);
+    }
+
+    unsafe extern "C" fn on_send_complete(user_data: *mut c_void, status: c_int) {
+        let sender = &*(user_data as *const mpsc::Sender<c_int>);
+        let _ = sender.send(status);
+    }
+
+    unsafe extern "C" fn release_payload(owner: *mut c_void) {
+        let sender = &*(owner as *const mpsc::Sender<c_int>);
+        let _ = sender.send(-1);
+    }
+
+    unsafe extern "C" fn on_peer_connected(user_data: *mut c_void, peer: *const c_char) {
+        let sender = &*(user_data as *const mpsc::Sender<String>);
+        let peer = CStr::from_ptr(peer).to_str().unwrap();
+        let _ = sender.send(format!("connected {peer}"));
+    }
+
+    /// Copy a config to a temporary file, binding to a random port.
+    fn init_server(
+        config_file: &str,
+        temp_name: &str,
+        callbacks: Option<&QuicnetCallbacks>,
+    ) -> *mut QuicnetServer {
+        let path = temp_config(config_file, temp_name);
+        let mut server = ptr::null_mut();
+        let callbacks = callbacks.map_or(ptr::null(), |c| c as *const _);
+        let status =
+            unsafe { quicnet_server_init_from_file(path.as_ptr(), 1, callbacks, &mut server) };
+        assert_eq!(status, QuicnetStatus::Ok as c_int);
+        server
+    }
+
+    fn init_server_with_queue(config_file: &str, temp_name: &str) -> *mut QuicnetServer {
+        let path = temp_config(config_file, temp_name);
+        let mut server = ptr::null_mut();
+        let status = unsafe { quicnet_server_init_with_queue(path.as_ptr(), 1, 16, &mut server) };
+        assert_eq!(status, QuicnetStatus::Ok as c_int);
+        server
+    }
+
+    /// Copy a config to a temporary file, binding to a random port.
+    fn temp_config(config_file: &str, temp_name: &str) -> CString {
+        let config = std::fs::read_to_string(config_file).expect("failed to read config");
+        // top-level keys must precede tables
+        let config = std::iter::once("addr = \"127.0.0.1:0\"")
+            .chain(config.lines().filter(|line| !line.starts_with("addr")))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let path = std::env::temp_dir().join(temp_name);
+        std::fs::write(&path, config).expect("failed to write config");
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    /// Whether `fd` is readable within `timeout_ms`.
+    fn readable(fd: c_int, timeout_ms: c_int) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, timeout_ms) == 1 }
+    }
+}
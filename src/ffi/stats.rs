@@ -0,0 +1,172 @@
+use super::{buffer::QuicnetBuffer, c_dns_name, ffi_call, server::QuicnetServer, QuicnetStatus};
+use crate::{
+    config::quic::CongestionControl,
+    server::{EndpointStats, PeerStats},
+};
+use std::ffi::{c_char, c_int};
+
+/// Congestion controller of a connection.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuicnetCongestionControl {
+    Cubic = 0,
+    NewReno = 1,
+    Bbr = 2,
+}
+
+/// Snapshot of the connection to a peer.
+#[repr(C)]
+pub struct QuicnetPeerStats {
+    pub congestion_control: QuicnetCongestionControl,
+    /// Current round-trip time estimate in microseconds.
+    pub rtt_us: u64,
+    /// Current congestion window in bytes.
+    pub cwnd: u64,
+    pub congestion_events: u64,
+    /// QUIC packets sent.
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    /// UDP datagrams and bytes sent and received, including QUIC overhead.
+    pub sent_datagrams: u64,
+    pub sent_bytes: u64,
+    pub received_datagrams: u64,
+    pub received_bytes: u64,
+    pub black_holes_detected: u64,
+    /// Proxy of the path MTU: the largest datagram payload fitting it,
+    /// the MTU less the QUIC packet overhead.
+    /// 0 if datagrams are not supported by both sides.
+    pub path_mtu_proxy: usize,
+}
+
+/// Aggregate over all connected peers.
+#[repr(C)]
+pub struct QuicnetEndpointStats {
+    pub peers: usize,
+    /// Largest round-trip time of any peer in microseconds.
+    pub max_rtt_us: u64,
+    pub congestion_events: u64,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    pub sent_datagrams: u64,
+    pub sent_bytes: u64,
+    pub received_datagrams: u64,
+    pub received_bytes: u64,
+}
+
+/// Write a snapshot of the connection to `peer` to `out`.
+/// Returns `QUICNET_STATUS_UNKNOWN_PEER` if not connected.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `peer` a nul-terminated string,
+/// `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_peer_stats(
+    server: *const QuicnetServer,
+    peer: *const c_char,
+    out: *mut QuicnetPeerStats,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let peer = c_dns_name(peer)?;
+        if out.is_null() {
+            return Err(QuicnetStatus::InvalidArgument);
+        }
+        let stats = server
+            .server
+            .peer_stats(&peer)
+            .ok_or(QuicnetStatus::UnknownPeer)?;
+        out.write(QuicnetPeerStats::from(&stats));
+        Ok(())
+    })
+}
+
+/// Write the aggregate over all connected peers to `out`.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_endpoint_stats(
+    server: *const QuicnetServer,
+    out: *mut QuicnetEndpointStats,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        if out.is_null() {
+            return Err(QuicnetStatus::InvalidArgument);
+        }
+        out.write(QuicnetEndpointStats::from(&server.server.endpoint_stats()));
+        Ok(())
+    })
+}
+
+/// Write the metrics in the Prometheus text exposition format to `out`,
+/// as a buffer that is not nul-terminated. The caller must release it.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_metrics(
+    server: *const QuicnetServer,
+    out: *mut QuicnetBuffer,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        if out.is_null() {
+            return Err(QuicnetStatus::InvalidArgument);
+        }
+        out.write(QuicnetBuffer::from_bytes(server.server.metrics().into()));
+        Ok(())
+    })
+}
+
+impl From<CongestionControl> for QuicnetCongestionControl {
+    fn from(c: CongestionControl) -> Self {
+        match c {
+            CongestionControl::Cubic => QuicnetCongestionControl::Cubic,
+            CongestionControl::NewReno => QuicnetCongestionControl::NewReno,
+            CongestionControl::Bbr => QuicnetCongestionControl::Bbr,
+        }
+    }
+}
+
+impl From<&PeerStats> for QuicnetPeerStats {
+    fn from(s: &PeerStats) -> Self {
+        Self {
+            congestion_control: s.congestion_control.into(),
+            rtt_us: s.rtt.as_micros() as u64,
+            cwnd: s.cwnd,
+            congestion_events: s.congestion_events,
+            sent_packets: s.sent_packets,
+            lost_packets: s.lost_packets,
+            lost_bytes: s.lost_bytes,
+            sent_datagrams: s.sent_datagrams,
+            sent_bytes: s.sent_bytes,
+            received_datagrams: s.received_datagrams,
+            received_bytes: s.received_bytes,
+            black_holes_detected: s.black_holes_detected,
+            path_mtu_proxy: s.path_mtu_proxy.unwrap_or(0),
+        }
+    }
+}
+
+impl From<&EndpointStats> for QuicnetEndpointStats {
+    fn from(s: &EndpointStats) -> Self {
+        Self {
+            peers: s.peers,
+            max_rtt_us: s.max_rtt.as_micros() as u64,
+            congestion_events: s.congestion_events,
+            sent_packets: s.sent_packets,
+            lost_packets: s.lost_packets,
+            lost_bytes: s.lost_bytes,
+            sent_datagrams: s.sent_datagrams,
+            sent_bytes: s.sent_bytes,
+            received_datagrams: s.received_datagrams,
+            received_bytes: s.received_bytes,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use super::{
+    c_str, ffi_call,
+    server::{command, QuicnetServer},
+    QuicnetStatus,
+};
+use crate::{
+    config::{domain_name::DomainPattern, whitelist::WhitelistUpdate},
+    server::ServerCommand,
+};
+use std::ffi::{c_char, c_int};
+use tokio::sync::oneshot;
+
+/// Permit the domain pattern `domain`, e.g. `*.cluster.example`,
+/// for new connections in both directions.
+/// No effect while the whitelist is disabled.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `domain` a nul-terminated string.
+/// Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_whitelist_add(
+    server: *const QuicnetServer,
+    domain: *const c_char,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let update = WhitelistUpdate::Add(vec![c_pattern(domain)?]);
+        update_whitelist(server, update, false)
+    })
+}
+
+/// Remove the domain pattern `domain`, rejecting peers it permitted for new
+/// connections in both directions. With `close_revoked`, existing connections
+/// to those peers, in either direction, are closed.
+/// No effect while the whitelist is disabled.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `domain` a nul-terminated string.
+/// Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_whitelist_remove(
+    server: *const QuicnetServer,
+    domain: *const c_char,
+    close_revoked: bool,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        let update = WhitelistUpdate::Remove(vec![c_pattern(domain)?]);
+        update_whitelist(server, update, close_revoked)
+    })
+}
+
+/// Replace the whitelist with the `n_domains` patterns at `domains`, for new
+/// connections in both directions. With `close_revoked`, existing connections
+/// to peers no longer permitted, in either direction, are closed.
+/// An empty list rejects all peers.
+///
+/// # Safety
+///
+/// `server` must be a live handle, `domains` null or pointing to `n_domains`
+/// nul-terminated strings. Must not be called from a server runtime thread.
+#[no_mangle]
+pub unsafe extern "C" fn quicnet_server_whitelist_replace(
+    server: *const QuicnetServer,
+    domains: *const *const c_char,
+    n_domains: usize,
+    close_revoked: bool,
+) -> c_int {
+    ffi_call(|| {
+        let server = server.as_ref().ok_or(QuicnetStatus::InvalidArgument)?;
+        if domains.is_null() && n_domains > 0 {
+            return Err(QuicnetStatus::InvalidArgument);
+        }
+        let domains = (0..n_domains)
+            .map(|i| c_pattern(*domains.add(i)))
+            .collect::<Result<_, _>>()?;
+        update_whitelist(
+            server,
+            WhitelistUpdate::Replace(Some(domains)),
+            close_revoked,
+        )
+    })
+}
+
+fn update_whitelist(
+    server: &QuicnetServer,
+    update: WhitelistUpdate,
+    close_revoked: bool,
+) -> Result<(), QuicnetStatus> {
+    let (reply, result) = oneshot::channel();
+    command(
+        server,
+        ServerCommand::UpdateWhitelist {
+            update,
+            close_revoked,
+            reply,
+        },
+    )?;
+    result
+        .blocking_recv()
+        .map(|_| ())
+        .map_err(|_| QuicnetStatus::Stopped)
+}
+
+/// Parse a nul-terminated whitelist entry, see `DomainPattern`.
+///
+/// # Safety
+///
+/// `ptr` must be null or a valid nul-terminated string.
+unsafe fn c_pattern(ptr: *const c_char) -> Result<DomainPattern, QuicnetStatus> {
+    c_str(ptr)?
+        .parse()
+        .map_err(|_| QuicnetStatus::InvalidArgument)
+}
@@ -1,2 +1,21 @@
+// the baseline config tests pass references by reference
+#![cfg_attr(test, allow(clippy::needless_borrow))]
+
 mod config;
+mod error;
+mod ffi;
+mod metrics;
 mod server;
+
+pub use config::{
+    domain_name::DomainPattern,
+    pins::{PeerConfig, SpkiPin},
+    quic::{CongestionControl, TransportConfig},
+    whitelist::WhitelistUpdate,
+    MetricsConfig, ServerConfig,
+};
+pub use error::QuicnetError;
+pub use server::{
+    DatagramError, Direction, EndpointStats, EventHandler, FrameError, PeerRegistry, PeerStats,
+    Responder, RpcError, SendError, Server, ServerCommand, ServerEvent,
+};
@@ -0,0 +1,381 @@
+//! Counters and histograms of the server, rendered in the Prometheus text format.
+//!
+//! Metrics are always collected. They are exposed through `Server::metrics`,
+//! and served over HTTP on `metrics.addr` if configured.
+use crate::server::Direction;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+/// Pause after a failed accept, so that persistent errors such as running
+/// out of file descriptors do not spin the loop.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    handshakes_attempted: PerDirection,
+    handshakes_succeeded: PerDirection,
+    /// Keyed by direction and reason.
+    handshakes_failed: Labeled<(&'static str, &'static str)>,
+    /// Keyed by reason.
+    client_certs_rejected: Labeled<&'static str>,
+    revoked_certificates: PerDirection,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    message_bytes_sent: AtomicU64,
+    message_bytes_received: AtomicU64,
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    datagram_bytes_sent: AtomicU64,
+    datagram_bytes_received: AtomicU64,
+    /// Keyed by `ok` or the error kind.
+    requests: Labeled<&'static str>,
+    request_latency: Histogram,
+}
+
+impl Metrics {
+    pub(crate) fn handshake_attempted(&self, direction: Direction) {
+        self.handshakes_attempted.inc(direction);
+    }
+
+    pub(crate) fn handshake_succeeded(&self, direction: Direction) {
+        self.handshakes_succeeded.inc(direction);
+    }
+
+    pub(crate) fn handshake_failed(&self, direction: Direction, reason: &'static str) {
+        self.handshakes_failed
+            .inc((direction_label(direction), reason));
+    }
+
+    /// A client certificate rejected by the verifier, before the handshake fails.
+    pub(crate) fn client_cert_rejected(&self, reason: &'static str) {
+        self.client_certs_rejected.inc(reason);
+    }
+
+    /// A revoked certificate presented by a client (inbound) or server (outbound).
+    pub(crate) fn revoked_certificate(&self, direction: Direction) {
+        self.revoked_certificates.inc(direction);
+    }
+
+    pub(crate) fn message_sent(&self, len: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.message_bytes_sent
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn message_received(&self, len: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.message_bytes_received
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn datagram_sent(&self, len: usize) {
+        self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+        self.datagram_bytes_sent
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn datagram_received(&self, len: usize) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+        self.datagram_bytes_received
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// A finished request, `result` is `ok` or the error kind.
+    pub(crate) fn request_finished(&self, result: &'static str, latency: Duration) {
+        self.requests.inc(result);
+        self.request_latency.observe(latency);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self, active_connections: usize) -> String {
+        let mut out = String::new();
+        self.handshakes_attempted.render(
+            &mut out,
+            "quicnet_handshakes_attempted_total",
+            "Handshakes started.",
+        );
+        self.handshakes_succeeded.render(
+            &mut out,
+            "quicnet_handshakes_succeeded_total",
+            "Handshakes completed with an identified peer.",
+        );
+        self.handshakes_failed.render(
+            &mut out,
+            "quicnet_handshakes_failed_total",
+            "Handshakes failed, by reason.",
+            |out, (direction, reason)| write!(out, "direction=\"{direction}\",reason=\"{reason}\""),
+        );
+        self.client_certs_rejected.render(
+            &mut out,
+            "quicnet_client_certs_rejected_total",
+            "Client certificates rejected by the verifier, by reason.",
+            |out, reason| write!(out, "reason=\"{reason}\""),
+        );
+        self.revoked_certificates.render(
+            &mut out,
+            "quicnet_revoked_certificates_total",
+            "Revoked certificates presented by peers, by direction of the connection.",
+        );
+        gauge(
+            &mut out,
+            "quicnet_active_connections",
+            "Registered peer connections.",
+            active_connections as u64,
+        );
+        for (name, help, value) in [
+            (
+                "quicnet_messages_sent_total",
+                "Messages sent.",
+                &self.messages_sent,
+            ),
+            (
+                "quicnet_messages_received_total",
+                "Messages received.",
+                &self.messages_received,
+            ),
+            (
+                "quicnet_message_bytes_sent_total",
+                "Payload bytes of messages sent.",
+                &self.message_bytes_sent,
+            ),
+            (
+                "quicnet_message_bytes_received_total",
+                "Payload bytes of messages received.",
+                &self.message_bytes_received,
+            ),
+            (
+                "quicnet_datagrams_sent_total",
+                "Datagrams sent.",
+                &self.datagrams_sent,
+            ),
+            (
+                "quicnet_datagrams_received_total",
+                "Datagrams received.",
+                &self.datagrams_received,
+            ),
+            (
+                "quicnet_datagram_bytes_sent_total",
+                "Payload bytes of datagrams sent.",
+                &self.datagram_bytes_sent,
+            ),
+            (
+                "quicnet_datagram_bytes_received_total",
+                "Payload bytes of datagrams received.",
+                &self.datagram_bytes_received,
+            ),
+        ] {
+            counter(&mut out, name, help, value.load(Ordering::Relaxed));
+        }
+        self.requests.render(
+            &mut out,
+            "quicnet_requests_total",
+            "Requests sent, by result.",
+            |out, result| write!(out, "result=\"{result}\""),
+        );
+        self.request_latency.render(
+            &mut out,
+            "quicnet_request_latency_seconds",
+            "Latency of requests sent, until the response or error.",
+        );
+        out
+    }
+}
+
+fn direction_label(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Inbound => "inbound",
+        Direction::Outbound => "outbound",
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+#[derive(Default)]
+struct PerDirection {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl PerDirection {
+    fn inc(&self, direction: Direction) {
+        match direction {
+            Direction::Inbound => &self.inbound,
+            Direction::Outbound => &self.outbound,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "counter");
+        for (direction, value) in [("inbound", &self.inbound), ("outbound", &self.outbound)] {
+            let value = value.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}{{direction=\"{direction}\"}} {value}");
+        }
+    }
+}
+
+/// Counters keyed by label values, created on first use.
+struct Labeled<K>(Mutex<BTreeMap<K, u64>>);
+
+impl<K> Default for Labeled<K> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<K: Ord + Copy> Labeled<K> {
+    fn inc(&self, key: K) {
+        let mut counters = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        *counters.entry(key).or_default() += 1;
+    }
+
+    fn render<F>(&self, out: &mut String, name: &str, help: &str, labels: F)
+    where
+        F: Fn(&mut String, K) -> std::fmt::Result,
+    {
+        header(out, name, help, "counter");
+        let counters = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, value) in counters.iter() {
+            let _ = write!(out, "{name}{{");
+            let _ = labels(out, *key);
+            let _ = writeln!(out, "}} {value}");
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut count = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            match LATENCY_BUCKETS.get(i) {
+                Some(le) => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+                }
+                None => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+                }
+            }
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// Answer every HTTP request on `listener` with the output of `render`.
+pub(crate) async fn serve<F>(listener: TcpListener, render: F)
+where
+    F: Fn() -> String,
+{
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!("failed to accept metrics connection: {e}");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let body = render();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        // the request is not parsed, any path returns the metrics
+        tokio::spawn(async move {
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                tracing::debug!("failed to write metrics: {e}");
+            }
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.handshake_attempted(Direction::Inbound);
+        metrics.handshake_failed(Direction::Inbound, "unknown_issuer");
+        metrics.client_cert_rejected("unknown_issuer");
+        metrics.revoked_certificate(Direction::Outbound);
+        metrics.message_sent(5);
+        metrics.request_finished("ok", Duration::from_millis(3));
+        metrics.request_finished("timeout", Duration::from_secs(10));
+        let text = metrics.render(2);
+        for line in [
+            "quicnet_handshakes_attempted_total{direction=\"inbound\"} 1",
+            "quicnet_handshakes_attempted_total{direction=\"outbound\"} 0",
+            "quicnet_handshakes_failed_total{direction=\"inbound\",reason=\"unknown_issuer\"} 1",
+            "quicnet_client_certs_rejected_total{reason=\"unknown_issuer\"} 1",
+            "quicnet_revoked_certificates_total{direction=\"outbound\"} 1",
+            "quicnet_active_connections 2",
+            "quicnet_message_bytes_sent_total 5",
+            "quicnet_requests_total{result=\"timeout\"} 1",
+            "quicnet_request_latency_seconds_bucket{le=\"0.0025\"} 0",
+            "quicnet_request_latency_seconds_bucket{le=\"0.005\"} 1",
+            "quicnet_request_latency_seconds_bucket{le=\"+Inf\"} 2",
+            "quicnet_request_latency_seconds_sum 10.003",
+            "quicnet_request_latency_seconds_count 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use super::{
+    connect::{handshake_error, handshake_failure_reason},
+    event::ServerEvent,
+    registry::{name, peer_name, Direction},
+    streams::serve,
+    ServerState, UNKNOWN_PEER_CODE,
+};
+use quinn::Connecting;
+use std::sync::Arc;
+
+/// Accept incoming connections until the endpoint is closed.
+pub(super) async fn accept_loop(state: Arc<ServerState>) {
+    while let Some(connecting) = state.endpoint.accept().await {
+        tokio::spawn(handle_incoming(state.clone(), connecting));
+    }
+    tracing::debug!("accept loop stopped");
+}
+
+/// Complete the handshake of an incoming connection and register the peer.
+async fn handle_incoming(state: Arc<ServerState>, connecting: Connecting) {
+    let addr = connecting.remote_address();
+    let metrics = &state.metrics;
+    metrics.handshake_attempted(Direction::Inbound);
+    // client certificate is verified by `AllowWhitelistAuthenticatedClient` during handshake
+    let conn = match connecting.await {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::warn!("handshake with {addr} failed: {e}");
+            metrics.handshake_failed(Direction::Inbound, handshake_failure_reason(&e));
+            state.handler.on_event(ServerEvent::Error {
+                error: handshake_error(addr, None, e),
+            });
+            return;
+        }
+    };
+    let whitelist = state.whitelist.load();
+    let peer = match peer_name(&conn, whitelist.as_deref().map(Vec::as_slice)) {
+        Ok(peer) => peer,
+        Err(e) => {
+            tracing::warn!("failed to resolve peer name of {addr}: {e}");
+            conn.close(UNKNOWN_PEER_CODE, b"unknown peer");
+            metrics.handshake_failed(Direction::Inbound, "unknown_peer");
+            state.handler.on_event(ServerEvent::Error { error: e });
+            return;
+        }
+    };
+    tracing::info!("accepted connection from {} ({addr})", name(&peer));
+    metrics.handshake_succeeded(Direction::Inbound);
+    let messages = state
+        .peers
+        .register(peer.clone(), conn.clone(), Direction::Inbound);
+    serve(state, peer, conn, messages).await;
+}
@@ -0,0 +1,121 @@
+use super::{
+    registry::{name, peer_name, Direction},
+    streams::serve,
+    ServerState,
+};
+use crate::{config::domain_name::DomainPattern, error::QuicnetError};
+use quinn::{ConnectError, Connecting, ConnectionError};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::oneshot;
+use webpki::DnsName;
+
+/// Complete the connection `connecting` dialed to `addr`, verify that the
+/// server is `domain` and register the connection.
+///
+/// The result is sent to `reply`. Losing duplicate resolution against an
+/// existing connection to the same peer is not a failure.
+pub(super) async fn connect(
+    state: Arc<ServerState>,
+    addr: SocketAddr,
+    domain: DnsName,
+    connecting: Result<Connecting, ConnectError>,
+    reply: oneshot::Sender<Result<(), QuicnetError>>,
+) {
+    let result = dial(&state, addr, domain, connecting).await;
+    if let Err(e) = &result {
+        tracing::warn!("failed to connect to {addr}: {e}");
+    }
+    // the caller may not wait for the result
+    let _ = reply.send(result);
+}
+
+async fn dial(
+    state: &Arc<ServerState>,
+    addr: SocketAddr,
+    domain: DnsName,
+    connecting: Result<Connecting, ConnectError>,
+) -> Result<(), QuicnetError> {
+    let metrics = &state.metrics;
+    metrics.handshake_attempted(Direction::Outbound);
+    // the server certificate is verified against `domain` (SNI) during handshake
+    let connecting = connecting.map_err(|e| {
+        metrics.handshake_failed(Direction::Outbound, "connect");
+        QuicnetError::Connect {
+            addr,
+            source: Box::new(e),
+        }
+    })?;
+    let conn = connecting.await.map_err(|e| {
+        metrics.handshake_failed(Direction::Outbound, handshake_failure_reason(&e));
+        handshake_error(addr, Some(domain.clone()), e)
+    })?;
+    match peer_name(&conn, Some(&[DomainPattern::Exact(domain.clone())])) {
+        Ok(peer) => {
+            tracing::info!("connected to {} ({addr})", name(&peer));
+            metrics.handshake_succeeded(Direction::Outbound);
+            let messages = state
+                .peers
+                .register(peer.clone(), conn.clone(), Direction::Outbound);
+            tokio::spawn(serve(state.clone(), peer, conn, messages));
+            Ok(())
+        }
+        Err(e) => {
+            tracing::warn!("unexpected peer certificate from {addr}: {e}");
+            conn.close(super::UNKNOWN_PEER_CODE, b"unexpected peer");
+            metrics.handshake_failed(Direction::Outbound, "name_mismatch");
+            Err(QuicnetError::PeerVerification {
+                addr,
+                peer: Some(domain),
+                reason: e.to_string(),
+            })
+        }
+    }
+}
+
+/// Classify a failed handshake with `addr`.
+///
+/// TLS alerts, sent by either side, are reported as peer verification failures.
+pub(super) fn handshake_error(
+    addr: SocketAddr,
+    peer: Option<DnsName>,
+    e: ConnectionError,
+) -> QuicnetError {
+    match error_code(&e) {
+        // CRYPTO_ERROR range, carrying a TLS alert
+        Some(0x100..=0x1ff) => QuicnetError::PeerVerification {
+            addr,
+            peer,
+            reason: e.to_string(),
+        },
+        _ => QuicnetError::Connect {
+            addr,
+            source: Box::new(e),
+        },
+    }
+}
+
+/// Metrics label of a failed handshake, from the TLS alert if any.
+pub(super) fn handshake_failure_reason(e: &ConnectionError) -> &'static str {
+    match error_code(e) {
+        // unknown_ca
+        Some(0x130) => "unknown_issuer",
+        // certificate_expired, also sent for certificates not yet valid
+        Some(0x12d) => "expired",
+        // certificate_revoked
+        Some(0x12c) => "revoked",
+        // bad_certificate, e.g. a name not in the whitelist
+        Some(0x12a) => "bad_certificate",
+        Some(0x100..=0x1ff) => "tls",
+        _ if matches!(e, ConnectionError::TimedOut) => "timeout",
+        _ => "connection",
+    }
+}
+
+/// Transport error code of a connection closed by either side.
+fn error_code(e: &ConnectionError) -> Option<u64> {
+    match e {
+        ConnectionError::TransportError(e) => Some(u64::from(e.code)),
+        ConnectionError::ConnectionClosed(close) => Some(u64::from(close.error_code)),
+        _ => None,
+    }
+}
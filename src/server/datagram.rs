@@ -0,0 +1,103 @@
+use super::{event::ServerEvent, registry::name, ServerState};
+use bytes::Bytes;
+use quinn::{Connection, ConnectionError, SendDatagramError};
+use std::fmt::Display;
+use webpki::DnsName;
+
+/// Failure of sending a datagram.
+#[derive(Debug)]
+pub enum DatagramError {
+    /// No connection to the peer is registered.
+    UnknownPeer(DnsName),
+    /// The peer did not negotiate datagram support.
+    UnsupportedByPeer,
+    /// Datagram support is disabled locally.
+    Disabled,
+    /// The datagram exceeds the maximum datagram size of the connection.
+    TooLarge {
+        size: usize,
+        max: Option<usize>,
+    },
+    ConnectionLost(ConnectionError),
+}
+
+impl Display for DatagramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatagramError::UnknownPeer(peer) => write!(f, "not connected to {}", name(peer)),
+            DatagramError::UnsupportedByPeer => write!(f, "datagrams not supported by peer"),
+            DatagramError::Disabled => write!(f, "datagram support disabled"),
+            DatagramError::TooLarge {
+                size,
+                max: Some(max),
+            } => {
+                write!(
+                    f,
+                    "datagram of {size} bytes exceeds maximum datagram size {max}"
+                )
+            }
+            DatagramError::TooLarge { size, max: None } => {
+                write!(f, "datagram of {size} bytes too large")
+            }
+            DatagramError::ConnectionLost(e) => write!(f, "connection lost: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DatagramError {}
+
+/// Send an unreliable datagram to `peer`.
+pub(super) fn send_datagram(
+    state: &ServerState,
+    peer: &DnsName,
+    payload: Bytes,
+) -> Result<(), DatagramError> {
+    let conn = state
+        .peers
+        .get(peer)
+        .ok_or_else(|| DatagramError::UnknownPeer(peer.clone()))?;
+    let size = payload.len();
+    conn.send_datagram(payload)
+        .map(|()| state.metrics.datagram_sent(size))
+        .map_err(|e| match e {
+            SendDatagramError::UnsupportedByPeer => DatagramError::UnsupportedByPeer,
+            SendDatagramError::Disabled => DatagramError::Disabled,
+            SendDatagramError::TooLarge => DatagramError::TooLarge {
+                size,
+                max: conn.max_datagram_size(),
+            },
+            SendDatagramError::ConnectionLost(e) => DatagramError::ConnectionLost(e),
+        })
+}
+
+/// Maximum size of a datagram that can be sent to `peer`.
+pub(super) fn max_datagram_size(
+    state: &ServerState,
+    peer: &DnsName,
+) -> Result<usize, DatagramError> {
+    state
+        .peers
+        .get(peer)
+        .ok_or_else(|| DatagramError::UnknownPeer(peer.clone()))?
+        .max_datagram_size()
+        .ok_or(DatagramError::UnsupportedByPeer)
+}
+
+/// Deliver datagrams from `peer` to the application until the connection is closed.
+pub(super) async fn receive_datagrams(state: &ServerState, peer: &DnsName, conn: &Connection) {
+    loop {
+        match conn.read_datagram().await {
+            Ok(payload) => {
+                state.metrics.datagram_received(payload.len());
+                state.handler.on_event(ServerEvent::Datagram {
+                    peer: peer.clone(),
+                    payload,
+                })
+            }
+            Err(e) => {
+                tracing::debug!("stopped receiving datagrams from {}: {e}", name(peer));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use super::{registry::Direction, rpc::Responder};
+use crate::error::QuicnetError;
+use bytes::Bytes;
+use quinn::ConnectionError;
+use webpki::DnsName;
+
+/// Events delivered to the application.
+#[derive(Debug)]
+pub enum ServerEvent {
+    /// A message received from `peer`.
+    Message { peer: DnsName, payload: Bytes },
+    /// An unreliable datagram received from `peer`.
+    Datagram { peer: DnsName, payload: Bytes },
+    /// A request received from `peer`, answered through `responder`.
+    Request {
+        peer: DnsName,
+        payload: Bytes,
+        responder: Responder,
+    },
+    /// The first connection to `peer` is registered.
+    ///
+    /// Not repeated when a duplicate connection replaces the registered one.
+    PeerConnected { peer: DnsName, direction: Direction },
+    /// The last connection to `peer` is closed.
+    PeerDisconnected {
+        peer: DnsName,
+        reason: ConnectionError,
+    },
+    /// A failure not reported to any caller, such as a failed
+    /// incoming handshake or a rejected incoming frame.
+    Error { error: QuicnetError },
+}
+
+/// Receives server events.
+///
+/// Called from the server runtime threads, possibly concurrently,
+/// so implementations should not block.
+pub trait EventHandler: Send + Sync {
+    fn on_event(&self, event: ServerEvent);
+}
+
+impl<F> EventHandler for F
+where
+    F: Fn(ServerEvent) + Send + Sync,
+{
+    fn on_event(&self, event: ServerEvent) {
+        self(event)
+    }
+}
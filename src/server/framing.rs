@@ -0,0 +1,123 @@
+use bytes::Bytes;
+use quinn::{ReadExactError, RecvStream, SendStream};
+use std::fmt::Display;
+
+/// Size of the big-endian length prefix of a frame.
+const LEN_PREFIX: usize = 4;
+
+/// First byte written to a bidirectional stream, telling the receiver how to handle it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum StreamKind {
+    /// A long-lived stream of messages.
+    Message = 0,
+    /// A single request followed by its response.
+    Request = 1,
+}
+
+impl TryFrom<u8> for StreamKind {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(StreamKind::Message),
+            1 => Ok(StreamKind::Request),
+            v => Err(v),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// The frame exceeds the maximum frame size.
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// The stream finished in the middle of a frame.
+    Truncated,
+    Read(quinn::ReadError),
+    Write(quinn::WriteError),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::TooLarge { size, max } => {
+                write!(f, "frame of {size} bytes exceeds maximum frame size {max}")
+            }
+            FrameError::Truncated => write!(f, "stream finished in the middle of a frame"),
+            FrameError::Read(e) => write!(f, "failed to read frame: {e}"),
+            FrameError::Write(e) => write!(f, "failed to write frame: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Read the stream kind byte.
+pub(crate) async fn read_kind(recv: &mut RecvStream) -> Result<Result<StreamKind, u8>, FrameError> {
+    let mut kind = [0u8; 1];
+    match recv.read_exact(&mut kind).await {
+        Ok(()) => Ok(StreamKind::try_from(kind[0])),
+        Err(ReadExactError::FinishedEarly) => Err(FrameError::Truncated),
+        Err(ReadExactError::ReadError(e)) => Err(FrameError::Read(e)),
+    }
+}
+
+/// Write the stream kind byte.
+pub(crate) async fn write_kind(send: &mut SendStream, kind: StreamKind) -> Result<(), FrameError> {
+    send.write_all(&[kind as u8])
+        .await
+        .map_err(FrameError::Write)
+}
+
+/// Write a length-prefixed frame, without copying `payload`.
+pub(crate) async fn write_frame(
+    send: &mut SendStream,
+    payload: Bytes,
+    max: usize,
+) -> Result<(), FrameError> {
+    let len = check_size(payload.len(), max)?;
+    send.write_all_chunks(&mut [Bytes::copy_from_slice(&len.to_be_bytes()), payload])
+        .await
+        .map_err(FrameError::Write)
+}
+
+/// Read a length-prefixed frame.
+///
+/// Returns `None` if the stream finished at a frame boundary.
+/// Frames larger than `max` are rejected before reading their payload.
+pub(crate) async fn read_frame(
+    recv: &mut RecvStream,
+    max: usize,
+) -> Result<Option<Bytes>, FrameError> {
+    let mut len = [0u8; LEN_PREFIX];
+    let mut filled = 0;
+    while filled < LEN_PREFIX {
+        match recv.read(&mut len[filled..]).await {
+            Ok(Some(n)) => filled += n,
+            // finished at a frame boundary only if no byte of the prefix was read
+            Ok(None) if filled == 0 => return Ok(None),
+            Ok(None) => return Err(FrameError::Truncated),
+            Err(e) => return Err(FrameError::Read(e)),
+        }
+    }
+    let size = u32::from_be_bytes(len) as usize;
+    if size > max {
+        return Err(FrameError::TooLarge { size, max });
+    }
+    let mut payload = vec![0u8; size];
+    match recv.read_exact(&mut payload).await {
+        Ok(()) => Ok(Some(Bytes::from(payload))),
+        Err(ReadExactError::FinishedEarly) => Err(FrameError::Truncated),
+        Err(ReadExactError::ReadError(e)) => Err(FrameError::Read(e)),
+    }
+}
+
+fn check_size(size: usize, max: usize) -> Result<u32, FrameError> {
+    match u32::try_from(size) {
+        Ok(len) if size <= max => Ok(len),
+        _ => Err(FrameError::TooLarge { size, max }),
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::Notify;
+
+/// Counts in-flight stream operations, so that shutdown can drain them.
+#[derive(Default)]
+pub(crate) struct Inflight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks an operation as in-flight until dropped.
+pub(crate) struct InflightGuard(Arc<Inflight>);
+
+impl Inflight {
+    pub fn start(self: &Arc<Self>) -> InflightGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        InflightGuard(self.clone())
+    }
+
+    /// Wait until no operation is in-flight.
+    pub async fn wait_idle(&self) {
+        loop {
+            // create the future before checking to not miss a notification
+            let idle = self.idle.notified();
+            if self.count.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
@@ -0,0 +1,1687 @@
+mod accept;
+mod connect;
+mod datagram;
+mod event;
+mod framing;
+mod inflight;
+mod registry;
+mod reload;
+mod rpc;
+mod stats;
+mod streams;
+
+use std::{
+    any::Any,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::config::{
+    quic::{default_config, CongestionControl},
+    tls::{canonical_dns_name, load_certificates},
+    whitelist::{permits, Whitelist, WhitelistUpdate},
+    ServerConfig, DEFAULT_MAX_FRAME_SIZE,
+};
+use crate::error::QuicnetError;
+use crate::metrics::{self, Metrics};
+use bytes::Bytes;
+use inflight::Inflight;
+use quinn::{Endpoint, VarInt};
+use tokio::{
+    net::TcpListener,
+    runtime::Handle,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender, WeakUnboundedSender},
+        oneshot,
+    },
+};
+use tracing_subscriber::EnvFilter;
+use webpki::DnsName;
+
+pub use datagram::DatagramError;
+pub use event::{EventHandler, ServerEvent};
+pub use framing::FrameError;
+pub use registry::{Direction, PeerRegistry};
+pub use rpc::{Responder, RpcError};
+pub use stats::{EndpointStats, PeerStats};
+pub use streams::SendError;
+
+const NET_LOG: &str = "quicnet";
+const MAX_WORKER_THREADS: usize = 256;
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Application error code sent to peers on graceful shutdown.
+pub const SHUTDOWN_CODE: VarInt = VarInt::from_u32(0);
+/// Application error code sent to peers on abort.
+pub const ABORT_CODE: VarInt = VarInt::from_u32(1);
+/// Application error code sent to peers whose name cannot be resolved.
+pub const UNKNOWN_PEER_CODE: VarInt = VarInt::from_u32(2);
+/// Application error code of connections closed by duplicate resolution.
+pub const DUPLICATE_CODE: VarInt = VarInt::from_u32(3);
+/// Stream error code of frames exceeding the maximum frame size.
+pub const FRAME_TOO_LARGE_CODE: VarInt = VarInt::from_u32(4);
+/// Stream error code of streams with an unknown stream kind.
+pub const UNKNOWN_STREAM_CODE: VarInt = VarInt::from_u32(5);
+/// Stream error code of requests dropped without a response.
+pub const REQUEST_DROPPED_CODE: VarInt = VarInt::from_u32(6);
+/// Application error code of connections closed because the peer
+/// is no longer permitted by the whitelist.
+pub const REVOKED_CODE: VarInt = VarInt::from_u32(7);
+
+pub enum ServerCommand {
+    /// Close all connections immediately with `ABORT_CODE`.
+    Abort,
+    /// Stop accepting new connections, wait at most `drain_timeout`
+    /// for in-flight streams to finish and sent messages to be read,
+    /// then close with `SHUTDOWN_CODE`.
+    Shutdown { drain_timeout: Duration },
+    /// Dial `addr` with SNI `domain` and register the connection,
+    /// under the canonical name of the peer, see `PeerRegistry`.
+    /// The result is sent to `reply`.
+    Connect {
+        addr: SocketAddr,
+        domain: DnsName,
+        reply: oneshot::Sender<Result<(), QuicnetError>>,
+    },
+    /// Send `payload` to `peer` as a length-prefixed frame.
+    /// The result is sent to `reply`.
+    Send {
+        peer: DnsName,
+        payload: Bytes,
+        reply: oneshot::Sender<Result<(), SendError>>,
+    },
+    /// Send `payload` to `peer` as a request on a fresh stream,
+    /// and reply with the response received within `timeout`.
+    Request {
+        peer: DnsName,
+        payload: Bytes,
+        timeout: Duration,
+        reply: oneshot::Sender<Result<Bytes, RpcError>>,
+    },
+    /// Send `payload` to `peer` as an unreliable datagram.
+    /// The result is sent to `reply`.
+    SendDatagram {
+        peer: DnsName,
+        payload: Bytes,
+        reply: oneshot::Sender<Result<(), DatagramError>>,
+    },
+    /// Reply with a snapshot of the connection to `peer`,
+    /// `None` if not connected.
+    Stats {
+        peer: DnsName,
+        reply: oneshot::Sender<Option<PeerStats>>,
+    },
+    /// Reply with the aggregate over all connected peers.
+    EndpointStats {
+        reply: oneshot::Sender<EndpointStats>,
+    },
+    /// Change the whitelist of peers for new connections, in both directions.
+    /// With `close_revoked`, connections to peers no longer permitted,
+    /// in either direction, are closed with `REVOKED_CODE`.
+    /// Their names are sent to `reply`.
+    UpdateWhitelist {
+        update: WhitelistUpdate,
+        close_revoked: bool,
+        reply: oneshot::Sender<Vec<DnsName>>,
+    },
+    /// Re-read the TLS files for new connections, keeping existing ones.
+    /// Nothing is changed if they are invalid. The result is sent to `reply`.
+    ReloadTls {
+        reply: oneshot::Sender<Result<(), QuicnetError>>,
+    },
+}
+
+pub struct Server {
+    cmd_sender: UnboundedSender<ServerCommand>,
+    state: Arc<ServerState>,
+    runtime: Handle,
+    metrics_addr: Option<SocketAddr>,
+
+    // use has_joined to fence the join_handle,
+    // both should only be accessed by the `join` method.
+    has_joined: AtomicBool,
+    join_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// State shared by the tasks running on the server runtime.
+struct ServerState {
+    /// Has no default client config, outbound connections are dialed
+    /// by the command loop, see `Server::main`.
+    endpoint: Endpoint,
+    /// Permitted peers, shared with the certificate verifiers of both directions.
+    whitelist: Arc<Whitelist>,
+    peers: Arc<PeerRegistry>,
+    handler: Arc<dyn EventHandler>,
+    max_frame_size: usize,
+    congestion_control: CongestionControl,
+    inflight: Arc<Inflight>,
+    metrics: Arc<Metrics>,
+    config: ServerConfig,
+}
+
+impl Server {
+    pub fn init(
+        n_threads: usize,
+        config: ServerConfig,
+        handler: Arc<dyn EventHandler>,
+    ) -> Result<Self, QuicnetError> {
+        Server::init_logger();
+        let (cmd_sender, cmd_receiver) = Server::make_cmd_channel();
+        let runtime = Server::make_runtime(n_threads).map_err(QuicnetError::Runtime)?;
+        let metrics = Arc::<Metrics>::default();
+        let whitelist = Arc::new(Whitelist::new(config.whitelist.clone()));
+        // quinn and tokio listeners require a runtime context
+        let ((endpoint, client_config), metrics_listener) = {
+            let _guard = runtime.enter();
+            (
+                Server::make_endpoint(&config, whitelist.clone(), metrics.clone())?,
+                Server::make_metrics_listener(&config)?,
+            )
+        };
+        let state = Arc::new(ServerState {
+            endpoint,
+            metrics,
+            whitelist,
+            peers: Arc::new(PeerRegistry::new(
+                Server::local_name(&config)?,
+                handler.clone(),
+            )),
+            handler,
+            max_frame_size: config.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE),
+            congestion_control: config
+                .transport
+                .as_ref()
+                .and_then(|t| t.congestion_control)
+                .unwrap_or_default(),
+            inflight: Arc::default(),
+            config,
+        });
+        let metrics_addr = match metrics_listener {
+            Some(listener) => {
+                let addr = listener.local_addr().ok();
+                let metrics_state = state.clone();
+                runtime.spawn(metrics::serve(listener, move || {
+                    metrics_state.metrics.render(metrics_state.peers.len())
+                }));
+                addr
+            }
+            None => None,
+        };
+        let runtime_state = state.clone();
+        let commands = cmd_sender.downgrade();
+        let handle = runtime.handle().clone();
+        let join_handle = Mutex::new(Some(std::thread::spawn(move || {
+            runtime.block_on(Server::main(
+                runtime_state,
+                client_config,
+                commands,
+                cmd_receiver,
+            ));
+            tracing::info!("shutting down server");
+            runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+            tracing::info!("server stopped");
+        })));
+        Ok(Server {
+            cmd_sender,
+            state,
+            runtime: handle,
+            metrics_addr,
+            has_joined: AtomicBool::new(false),
+            join_handle,
+        })
+    }
+
+    /// Send a command to the server runtime.
+    pub fn command(&self, cmd: ServerCommand) -> std::io::Result<()> {
+        self.cmd_sender.send(cmd).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::NotConnected, "server already stopped")
+        })
+    }
+
+    /// Handle to the server runtime.
+    ///
+    /// Tasks spawned after the server stopped are dropped without running.
+    pub(crate) fn runtime(&self) -> &Handle {
+        &self.runtime
+    }
+
+    /// Connected peers.
+    pub fn peers(&self) -> &PeerRegistry {
+        &self.state.peers
+    }
+
+    /// Maximum size of a datagram that can currently be sent to `peer`.
+    pub fn max_datagram_size(&self, peer: &DnsName) -> Result<usize, DatagramError> {
+        datagram::max_datagram_size(&self.state, peer)
+    }
+
+    /// Snapshot of the connection to `peer`, `None` if not connected.
+    pub fn peer_stats(&self, peer: &DnsName) -> Option<PeerStats> {
+        stats::peer_stats(&self.state, peer)
+    }
+
+    /// Aggregate over all connected peers.
+    pub fn endpoint_stats(&self) -> EndpointStats {
+        stats::endpoint_stats(&self.state)
+    }
+
+    /// Metrics in the Prometheus text exposition format.
+    pub fn metrics(&self) -> String {
+        self.state.metrics.render(self.state.peers.len())
+    }
+
+    /// The address metrics are served on, if configured.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// The local address the server is bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.state.endpoint.local_addr()
+    }
+
+    /// Wait for the server thread to exit.
+    ///
+    /// Safe to call from multiple threads: concurrent callers block until
+    /// the thread has been joined. Only the first call reports the thread's
+    /// result (a panic is returned as an error), later calls return `Ok(())`.
+    pub fn join(&self) -> std::io::Result<()> {
+        if self.has_joined.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let mut join_handle = self.join_handle.lock().unwrap_or_else(|e| e.into_inner());
+        let result = match join_handle.take() {
+            Some(handle) => handle.join().map_err(panic_error),
+            None => Ok(()),
+        };
+        self.has_joined.store(true, Ordering::Release);
+        result
+    }
+
+    /// Like `join`, but gives up waiting after `timeout`.
+    ///
+    /// The thread is detached if it is still running at the deadline.
+    fn join_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let finished = match &*self.join_handle.lock().unwrap_or_else(|e| e.into_inner()) {
+                Some(handle) => handle.is_finished(),
+                None => true,
+            };
+            if finished {
+                return self.join();
+            }
+            if Instant::now() >= deadline {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "server thread did not exit in time",
+                ));
+            }
+            std::thread::sleep(JOIN_POLL_INTERVAL);
+        }
+    }
+
+    /// main loop
+    ///
+    /// Outbound connections are dialed with the loop's own endpoint handle,
+    /// whose default client config is replaced on reload. Reloads read the
+    /// files in their own task and send the new configs back to the loop.
+    async fn main(
+        state: Arc<ServerState>,
+        client_config: quinn::ClientConfig,
+        commands: WeakUnboundedSender<ServerCommand>,
+        mut cmd_receiver: UnboundedReceiver<ServerCommand>,
+    ) {
+        let mut dialer = state.endpoint.clone();
+        dialer.set_default_client_config(client_config);
+        let accept_loop = tokio::spawn(accept::accept_loop(state.clone()));
+        let watch_tls = state.config.tls_reload_interval_ms.map(|ms| {
+            tokio::spawn(reload::watch_tls(
+                state.clone(),
+                commands,
+                Duration::from_millis(ms.max(1)),
+            ))
+        });
+        let reloading = Arc::new(tokio::sync::Mutex::new(()));
+        let (swaps, mut swap_receiver) = unbounded_channel::<reload::TlsSwap>();
+        loop {
+            let command = tokio::select! {
+                command = cmd_receiver.recv() => command,
+                Some(swap) = swap_receiver.recv() => {
+                    swap.apply(&state.endpoint, &mut dialer);
+                    continue;
+                }
+            };
+            match command {
+                Some(ServerCommand::Connect {
+                    addr,
+                    domain,
+                    reply,
+                }) => {
+                    let connecting = dialer.connect(addr, registry::name(&domain));
+                    tokio::spawn(connect::connect(
+                        state.clone(),
+                        addr,
+                        domain,
+                        connecting,
+                        reply,
+                    ));
+                }
+                Some(ServerCommand::Send {
+                    peer,
+                    payload,
+                    reply,
+                }) => {
+                    tokio::spawn(streams::send_message(state.clone(), peer, payload, reply));
+                }
+                Some(ServerCommand::Request {
+                    peer,
+                    payload,
+                    timeout,
+                    reply,
+                }) => {
+                    tokio::spawn(rpc::request(state.clone(), peer, payload, timeout, reply));
+                }
+                Some(ServerCommand::SendDatagram {
+                    peer,
+                    payload,
+                    reply,
+                }) => {
+                    let result = datagram::send_datagram(&state, &peer, payload);
+                    // the caller may not wait for the result
+                    let _ = reply.send(result);
+                }
+                Some(ServerCommand::Stats { peer, reply }) => {
+                    let _ = reply.send(stats::peer_stats(&state, &peer));
+                }
+                Some(ServerCommand::EndpointStats { reply }) => {
+                    let _ = reply.send(stats::endpoint_stats(&state));
+                }
+                Some(ServerCommand::UpdateWhitelist {
+                    update,
+                    close_revoked,
+                    reply,
+                }) => {
+                    let _ = reply.send(Server::update_whitelist(&state, update, close_revoked));
+                }
+                Some(ServerCommand::ReloadTls { reply }) => {
+                    tokio::spawn(reload::reload_tls(
+                        state.clone(),
+                        reloading.clone(),
+                        swaps.clone(),
+                        reply,
+                    ));
+                }
+                Some(ServerCommand::Shutdown { drain_timeout }) => {
+                    accept_loop.abort();
+                    if let Some(watch_tls) = &watch_tls {
+                        watch_tls.abort();
+                    }
+                    Server::shutdown(&state, drain_timeout).await;
+                    return;
+                }
+                // aborted, or all command senders dropped
+                Some(ServerCommand::Abort) | None => {
+                    accept_loop.abort();
+                    if let Some(watch_tls) = &watch_tls {
+                        watch_tls.abort();
+                    }
+                    Server::abort(&state.endpoint).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Apply `update`, returning the revoked peers closed with `close_revoked`.
+    fn update_whitelist(
+        state: &ServerState,
+        update: WhitelistUpdate,
+        close_revoked: bool,
+    ) -> Vec<DnsName> {
+        let whitelist = state.whitelist.update(update);
+        tracing::info!("updated whitelist: {whitelist:?}");
+        if !close_revoked {
+            return Vec::new();
+        }
+        let revoked: Vec<_> = state
+            .peers
+            .iter()
+            .filter(|(_, conn)| {
+                registry::peer_certificate(conn).is_none_or(|cert| !permits(&whitelist, &cert))
+            })
+            .collect();
+        for (peer, conn) in &revoked {
+            tracing::info!(
+                "closing connection to revoked peer {}",
+                registry::name(peer)
+            );
+            conn.close(REVOKED_CODE, b"revoked");
+        }
+        revoked.into_iter().map(|(peer, _)| peer).collect()
+    }
+
+    /// Close all connections immediately.
+    async fn abort(endpoint: &Endpoint) {
+        tracing::info!("aborting server");
+        endpoint.close(ABORT_CODE, b"abort");
+        Server::wait_idle(endpoint).await;
+    }
+
+    /// Graceful shutdown.
+    ///
+    /// New connections are refused right away, while in-flight streams
+    /// are given `drain_timeout` to finish before connections are closed.
+    /// Message streams are then finished, so that peers read every message
+    /// written before the shutdown.
+    async fn shutdown(state: &ServerState, drain_timeout: Duration) {
+        tracing::info!("draining server (timeout = {drain_timeout:?})");
+        state.endpoint.reject_new_connections();
+        let drain = async {
+            state.inflight.wait_idle().await;
+            state.peers.finish_messages(SHUTDOWN_CODE).await;
+        };
+        if tokio::time::timeout(drain_timeout, drain).await.is_err() {
+            tracing::warn!("drain timeout reached, closing remaining streams");
+        }
+        state.endpoint.close(SHUTDOWN_CODE, b"shutdown");
+        Server::wait_idle(&state.endpoint).await;
+    }
+
+    /// Wait for closed connections to notify peers, bounded by `SHUTDOWN_TIMEOUT`.
+    async fn wait_idle(endpoint: &Endpoint) {
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, endpoint.wait_idle())
+            .await
+            .is_err()
+        {
+            tracing::warn!("endpoint not idle after {SHUTDOWN_TIMEOUT:?}");
+        }
+    }
+
+    fn make_endpoint(
+        config: &ServerConfig,
+        whitelist: Arc<Whitelist>,
+        metrics: Arc<Metrics>,
+    ) -> Result<(Endpoint, quinn::ClientConfig), QuicnetError> {
+        let (server_config, client_config) = default_config(config, whitelist, metrics)?;
+        let endpoint =
+            Endpoint::server(server_config, config.addr).map_err(|source| QuicnetError::Bind {
+                addr: config.addr,
+                source,
+            })?;
+        Ok((endpoint, client_config))
+    }
+
+    fn make_metrics_listener(config: &ServerConfig) -> Result<Option<TcpListener>, QuicnetError> {
+        let Some(metrics) = &config.metrics else {
+            return Ok(None);
+        };
+        let error = |source| QuicnetError::Bind {
+            addr: metrics.addr,
+            source,
+        };
+        let listener = std::net::TcpListener::bind(metrics.addr).map_err(error)?;
+        listener.set_nonblocking(true).map_err(error)?;
+        TcpListener::from_std(listener).map(Some).map_err(error)
+    }
+
+    /// Canonical name of this server, the smallest DNS name in its certificate.
+    fn local_name(config: &ServerConfig) -> Result<DnsName, QuicnetError> {
+        let error = |reason: String| QuicnetError::CertificateLoad {
+            path: config.certs.clone(),
+            reason,
+        };
+        let certs = load_certificates(&config.certs, config.certs_password.as_ref())?;
+        let cert = certs
+            .first()
+            .ok_or_else(|| error("no certificate found".to_string()))?;
+        canonical_dns_name(cert)
+            .map_err(|e| error(format!("failed to parse certificate: {e}")))?
+            .ok_or_else(|| error("no domain name found in certificate".to_string()))
+    }
+
+    fn init_logger() {
+        let _ = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::builder().with_env_var(NET_LOG).from_env_lossy())
+            .try_init();
+    }
+
+    fn make_cmd_channel() -> (
+        UnboundedSender<ServerCommand>,
+        UnboundedReceiver<ServerCommand>,
+    ) {
+        unbounded_channel()
+    }
+
+    fn make_runtime(n_threads: usize) -> std::io::Result<tokio::runtime::Runtime> {
+        let n_thread = n_threads.min(MAX_WORKER_THREADS);
+        match n_thread {
+            0 => {
+                let n_cpu =
+                    std::thread::available_parallelism().unwrap_or(NonZeroUsize::new(1).unwrap());
+                tracing::info!("socket manager started runtime with {n_cpu} threads");
+                tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .worker_threads(n_cpu.get())
+                    .build()
+            }
+            1 => {
+                tracing::info!("socket manager started runtime with single thread");
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+            }
+            n => {
+                tracing::info!("socket manager started runtime with {n} threads");
+                tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .worker_threads(n)
+                    .build()
+            }
+        }
+    }
+}
+
+/// Shutdown the server gracefully when dropped.
+///
+/// Waits at most `3 * SHUTDOWN_TIMEOUT` (drain, close, runtime shutdown)
+/// before detaching the server thread.
+impl Drop for Server {
+    fn drop(&mut self) {
+        if self.has_joined.load(Ordering::Acquire) {
+            return;
+        }
+        // fails only if the server has already stopped
+        let _ = self.command(ServerCommand::Shutdown {
+            drain_timeout: SHUTDOWN_TIMEOUT,
+        });
+        if let Err(e) = self.join_timeout(3 * SHUTDOWN_TIMEOUT) {
+            tracing::error!("error stopping server on drop: {e}");
+        }
+    }
+}
+
+fn panic_error(panic: Box<dyn Any + Send>) -> std::io::Error {
+    let msg = if let Some(s) = panic.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown panic"
+    };
+    std::io::Error::other(format!("server thread panicked: {msg}"))
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use crate::config::{
+        domain_name::DomainPattern,
+        pins::{PeerConfig, SpkiPin},
+        MetricsConfig,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const NAME_A: &str = "ddpwuxrmp.uk";
+    const NAME_B: &str = "rehdhssj.cn";
+    /// The certificate of this name also lists `NAME_TWO_NAMES_CANONICAL`.
+    const NAME_TWO_NAMES: &str = "wmqxvtr.uk";
+    const NAME_TWO_NAMES_CANONICAL: &str = "bqfztnk.uk";
+    /// Revoked by `certs/crl.pem`.
+    const NAME_REVOKED: &str = "zqxbnvtk.uk";
+    /// Self-signed, not trusted by the CA.
+    const NAME_SELF_SIGNED: &str = "kmvrtxqe.uk";
+    const CONFIG_A: &str = "data/config-ddpwuxrmp.toml";
+    const CONFIG_B: &str = "data/config-rehdhssj.toml";
+    /// `NAME_B` with a `[transport]` section selecting BBR.
+    const CONFIG_TRANSPORT: &str = "data/config-transport.toml";
+
+    #[test]
+    fn test_shutdown_join() {
+        let server = make_server(CONFIG_A);
+        server
+            .command(ServerCommand::Shutdown {
+                drain_timeout: Duration::from_millis(100),
+            })
+            .expect("failed to send shutdown");
+        server.join().expect("failed to join");
+        // join is idempotent
+        server.join().expect("failed to join twice");
+        assert!(server.command(ServerCommand::Abort).is_err());
+    }
+
+    #[test]
+    fn test_abort_join() {
+        let server = make_server(CONFIG_A);
+        server
+            .command(ServerCommand::Abort)
+            .expect("failed to send abort");
+        server.join().expect("failed to join");
+    }
+
+    #[test]
+    fn test_drop() {
+        let start = Instant::now();
+        drop(make_server(CONFIG_A));
+        assert!(start.elapsed() < 3 * SHUTDOWN_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_accept_registry() {
+        let server_b = make_server(CONFIG_B);
+        let client_a = make_endpoint(CONFIG_A);
+        let conn = client_a
+            .connect(local_addr(&server_b), NAME_B)
+            .expect("failed to connect")
+            .await
+            .expect("failed connecting");
+        // wait for server to register the connection
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let name_a = dns_name(NAME_A);
+        assert_eq!(server_b.peers().len(), 1);
+        assert!(server_b.peers().get(&name_a).is_some());
+        let (peer, _) = server_b.peers().iter().next().unwrap();
+        assert_eq!(peer, name_a);
+        // entry removed once closed
+        conn.close(VarInt::from_u32(0), b"done");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server_b.peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_accept_rejected() {
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        // A only permits itself
+        config.whitelist = Some(vec![NAME_A.parse().unwrap()]);
+        let server_a = Server::init(
+            1,
+            config,
+            Arc::new(move |event| {
+                if let ServerEvent::Error { error } = event {
+                    let _ = events.send(error);
+                }
+            }),
+        )
+        .expect("failed to init server");
+        let client_b = make_endpoint(CONFIG_B);
+        // the client may complete its side of the handshake before A rejects it
+        let _ = client_b
+            .connect(local_addr(&server_a), NAME_A)
+            .expect("failed to connect")
+            .await;
+        let error = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("no error reported")
+            .unwrap();
+        assert!(matches!(
+            error,
+            QuicnetError::PeerVerification { peer: None, .. }
+        ));
+        assert!(server_a.peers().is_empty());
+        // the accept loop keeps serving permitted peers
+        let client_a = make_endpoint(CONFIG_A);
+        let _conn = client_a
+            .connect(local_addr(&server_a), NAME_A)
+            .expect("failed to connect")
+            .await
+            .expect("failed connecting");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server_a.peers().contains(&dns_name(NAME_A)));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_truncated_prefix() {
+        let endpoint_a = make_endpoint(CONFIG_A);
+        let endpoint_b = make_endpoint(CONFIG_B);
+        let addr_b = endpoint_b.local_addr().unwrap();
+        let (conn_a, conn_b) = tokio::join!(
+            async {
+                endpoint_a
+                    .connect(addr_b, NAME_B)
+                    .expect("failed to connect")
+                    .await
+                    .expect("failed connecting")
+            },
+            async {
+                endpoint_b
+                    .accept()
+                    .await
+                    .expect("endpoint closed")
+                    .await
+                    .expect("failed accepting")
+            }
+        );
+        // finishing before the prefix is a frame boundary, within it is not
+        for written in [&b""[..], b"\0\0"] {
+            let (mut send, _recv) = conn_a.open_bi().await.expect("failed to open stream");
+            send.write_all(written).await.expect("failed to write");
+            send.finish().await.expect("failed to finish");
+            let (_send, mut recv) = conn_b.accept_bi().await.expect("failed to accept stream");
+            let result = framing::read_frame(&mut recv, 1024).await;
+            if written.is_empty() {
+                assert!(matches!(result, Ok(None)));
+            } else {
+                assert!(matches!(result, Err(FrameError::Truncated)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let server_a = make_server(CONFIG_A);
+        let server_b = make_server(CONFIG_B);
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (name_a, name_b) = (dns_name(NAME_A), dns_name(NAME_B));
+        assert_eq!(
+            server_a.peers().direction(&name_b),
+            Some(Direction::Outbound)
+        );
+        assert_eq!(
+            server_b.peers().direction(&name_a),
+            Some(Direction::Inbound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_wrong_domain() {
+        let server_a = make_server(CONFIG_A);
+        let server_b = make_server(CONFIG_B);
+        // server B presents a certificate for NAME_B
+        let result = connect(&server_a, &server_b, NAME_A).await;
+        assert!(matches!(
+            result,
+            Err(QuicnetError::PeerVerification { peer: Some(peer), .. }) if peer == dns_name(NAME_A)
+        ));
+        assert!(server_a.peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_connect() {
+        let server_a = make_server_without_whitelist(CONFIG_A);
+        let server_b = make_server(CONFIG_B);
+        let (a_to_b, b_to_a) = tokio::join!(
+            connect(&server_a, &server_b, NAME_B),
+            connect(&server_b, &server_a, NAME_A),
+        );
+        a_to_b.expect("failed to connect a to b");
+        b_to_a.expect("failed to connect b to a");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let (name_a, name_b) = (dns_name(NAME_A), dns_name(NAME_B));
+        // both keep the connection dialed by the smaller name
+        assert_eq!(server_a.peers().len(), 1);
+        assert_eq!(server_b.peers().len(), 1);
+        assert_eq!(
+            server_a.peers().direction(&name_b),
+            Some(Direction::Outbound)
+        );
+        assert_eq!(
+            server_b.peers().direction(&name_a),
+            Some(Direction::Inbound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_connect_two_names() {
+        // W presents both names, its canonical name is the smaller one
+        let (name_a, name_w) = (dns_name(NAME_A), dns_name(NAME_TWO_NAMES_CANONICAL));
+        for dialed in [NAME_TWO_NAMES, NAME_TWO_NAMES_CANONICAL] {
+            let server_a = make_server_without_whitelist(CONFIG_A);
+            let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+            config.addr = "127.0.0.1:0".parse().unwrap();
+            config.certs = "./certs/wmqxvtr.uk/wmqxvtr.uk.crt".into();
+            config.key = "./certs/wmqxvtr.uk/wmqxvtr.uk.key".into();
+            config.whitelist = None;
+            let server_w =
+                Server::init(1, config, Arc::new(|_| {})).expect("failed to init server");
+            let (a_to_w, w_to_a) = tokio::join!(
+                connect(&server_a, &server_w, dialed),
+                connect(&server_w, &server_a, NAME_A),
+            );
+            a_to_w.expect("failed to connect a to w");
+            w_to_a.expect("failed to connect w to a");
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            // both keep the connection dialed by W
+            assert_eq!(server_a.peers().len(), 1, "dialed {dialed}");
+            assert_eq!(server_w.peers().len(), 1, "dialed {dialed}");
+            assert_eq!(
+                server_a.peers().direction(&name_w),
+                Some(Direction::Inbound)
+            );
+            assert_eq!(
+                server_w.peers().direction(&name_a),
+                Some(Direction::Outbound)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_connect_send() {
+        const COUNT: usize = 50;
+        let make_server = |config_file| {
+            let (events, received) = tokio::sync::mpsc::unbounded_channel();
+            let server = make_server_with_handler(
+                config_file,
+                Arc::new(move |event| {
+                    if let ServerEvent::Message { payload, .. } = event {
+                        let _ = events.send(payload);
+                    }
+                }),
+            );
+            (server, received)
+        };
+        let (server_a, mut received_a) = make_server(CONFIG_A);
+        let (server_b, mut received_b) = make_server(CONFIG_B);
+        let send_all = |from, peer| async move {
+            let sent: Vec<_> = (0..COUNT)
+                .map(|i| {
+                    // large enough to still be in flight when the duplicate is closed
+                    let mut payload = vec![0; 16 * 1024];
+                    payload[..8].copy_from_slice(&i.to_be_bytes());
+                    send_command(from, peer, Bytes::from(payload))
+                })
+                .collect();
+            for result in sent {
+                result
+                    .await
+                    .expect("send reply dropped")
+                    .expect("failed to send");
+            }
+        };
+        // B sends on its connection, which loses once A dials B
+        connect(&server_b, &server_a, NAME_A)
+            .await
+            .expect("failed to connect");
+        tokio::join!(send_all(&server_b, NAME_A), async {
+            connect(&server_a, &server_b, NAME_B)
+                .await
+                .expect("failed to connect");
+            send_all(&server_a, NAME_B).await
+        });
+        for received in [&mut received_a, &mut received_b] {
+            let mut payloads = Vec::new();
+            while payloads.len() < COUNT {
+                let payload = tokio::time::timeout(Duration::from_secs(5), received.recv())
+                    .await
+                    .expect("message lost")
+                    .unwrap();
+                payloads.push(usize::from_be_bytes(payload[..8].try_into().unwrap()));
+            }
+            payloads.sort();
+            assert_eq!(payloads, (0..COUNT).collect::<Vec<_>>());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(received_a.try_recv().is_err() && received_b.try_recv().is_err());
+        assert_eq!(
+            server_b.peers().direction(&dns_name(NAME_A)),
+            Some(Direction::Inbound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_connect_request() {
+        const COUNT: usize = 50;
+        let make_server = |config_file| {
+            let handled = Arc::new(AtomicUsize::new(0));
+            let counter = handled.clone();
+            let server = make_server_with_handler(
+                config_file,
+                Arc::new(move |event| {
+                    if let ServerEvent::Request {
+                        payload, responder, ..
+                    } = event
+                    {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                        // still unanswered when the duplicate is closed
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            responder.respond(payload).expect("failed to respond");
+                        });
+                    }
+                }),
+            );
+            (server, handled)
+        };
+        let (server_a, handled_a) = make_server(CONFIG_A);
+        let (server_b, handled_b) = make_server(CONFIG_B);
+        async fn request_all(from: &Server, peer: &str) {
+            let sent: Vec<_> = (0..COUNT)
+                .map(|i| {
+                    let (reply, result) = oneshot::channel();
+                    from.command(ServerCommand::Request {
+                        peer: dns_name(peer),
+                        payload: Bytes::from(i.to_string()),
+                        timeout: SHUTDOWN_TIMEOUT,
+                        reply,
+                    })
+                    .expect("failed to send request");
+                    result
+                })
+                .collect();
+            for (i, result) in sent.into_iter().enumerate() {
+                let response = result
+                    .await
+                    .expect("request reply dropped")
+                    .expect("request failed");
+                assert_eq!(response, i.to_string());
+            }
+        }
+        // B requests on its connection, which loses once A dials B
+        connect(&server_b, &server_a, NAME_A)
+            .await
+            .expect("failed to connect");
+        tokio::join!(request_all(&server_b, NAME_A), async {
+            connect(&server_a, &server_b, NAME_B)
+                .await
+                .expect("failed to connect");
+            request_all(&server_a, NAME_B).await
+        });
+        // every request was handled once
+        assert_eq!(handled_a.load(Ordering::Relaxed), COUNT);
+        assert_eq!(handled_b.load(Ordering::Relaxed), COUNT);
+        assert_eq!(
+            server_b.peers().direction(&dns_name(NAME_A)),
+            Some(Direction::Inbound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_delivers_messages() {
+        const COUNT: usize = 50;
+        let server_a = make_server(CONFIG_A);
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let server_b = make_server_with_handler(
+            CONFIG_B,
+            Arc::new(move |event| {
+                if let ServerEvent::Message { payload, .. } = event {
+                    let _ = events.send(payload);
+                }
+            }),
+        );
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        let sent: Vec<_> = (0..COUNT)
+            .map(|i| {
+                // large enough to still be in flight when the server shuts down
+                let mut payload = vec![0; 64 * 1024];
+                payload[..8].copy_from_slice(&i.to_be_bytes());
+                send_command(&server_a, NAME_B, Bytes::from(payload))
+            })
+            .collect();
+        server_a
+            .command(ServerCommand::Shutdown {
+                drain_timeout: SHUTDOWN_TIMEOUT,
+            })
+            .expect("failed to send shutdown");
+        for result in sent {
+            result
+                .await
+                .expect("send reply dropped")
+                .expect("failed to send");
+        }
+        let mut payloads = Vec::new();
+        while payloads.len() < COUNT {
+            let payload = tokio::time::timeout(Duration::from_secs(5), received.recv())
+                .await
+                .expect("message lost")
+                .unwrap();
+            payloads.push(usize::from_be_bytes(payload[..8].try_into().unwrap()));
+        }
+        payloads.sort();
+        assert_eq!(payloads, (0..COUNT).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_send_message() {
+        let server_a = make_server(CONFIG_A);
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let server_b = make_server_with_handler(
+            CONFIG_B,
+            Arc::new(move |event| {
+                if let ServerEvent::Message { .. } = event {
+                    let _ = events.send(event);
+                }
+            }),
+        );
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        for payload in ["hello", "", "world"] {
+            send(&server_a, NAME_B, Bytes::from(payload))
+                .await
+                .expect("failed to send");
+        }
+        for expected in ["hello", "", "world"] {
+            let Some(ServerEvent::Message { peer, payload }) = received.recv().await else {
+                panic!("missing message");
+            };
+            assert_eq!(peer, dns_name(NAME_A));
+            assert_eq!(payload, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_errors() {
+        let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.max_frame_size = Some(4);
+        let server_a = Server::init(1, config, Arc::new(|_| {})).expect("failed to init server");
+        let server_b = make_server(CONFIG_B);
+        let result = send(&server_a, NAME_B, Bytes::from("hi")).await;
+        assert!(matches!(result, Err(SendError::UnknownPeer(_))));
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        let result = send(&server_a, NAME_B, Bytes::from("hello")).await;
+        assert!(matches!(
+            result,
+            Err(SendError::Frame(FrameError::TooLarge { size: 5, max: 4 }))
+        ));
+        send(&server_a, NAME_B, Bytes::from("hi"))
+            .await
+            .expect("failed to send");
+    }
+
+    #[tokio::test]
+    async fn test_receive_too_large() {
+        let server_a = make_server(CONFIG_A);
+        let mut config = ServerConfig::load(CONFIG_B).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.max_frame_size = Some(4);
+        let server_b = Server::init(1, config, Arc::new(|_| {})).expect("failed to init server");
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        // the first frame is buffered before server B stops the stream
+        let _ = send(&server_a, NAME_B, Bytes::from("hello")).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let result = send(&server_a, NAME_B, Bytes::from("hello")).await;
+        assert!(matches!(
+            result,
+            Err(SendError::Frame(FrameError::Write(
+                quinn::WriteError::Stopped(FRAME_TOO_LARGE_CODE)
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        let server_a = make_server(CONFIG_A);
+        // echo requests, drop requests starting with `drop`, hold the others
+        let held = Arc::new(Mutex::new(Vec::new()));
+        let held_requests = held.clone();
+        let server_b = make_server_with_handler(
+            CONFIG_B,
+            Arc::new(move |event| {
+                if let ServerEvent::Request {
+                    payload, responder, ..
+                } = event
+                {
+                    if payload.starts_with(b"echo") {
+                        responder.respond(payload).expect("failed to respond");
+                    } else if !payload.starts_with(b"drop") {
+                        held_requests.lock().unwrap().push(responder);
+                    }
+                }
+            }),
+        );
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        let timeout = Duration::from_millis(500);
+        let response = request(&server_a, NAME_B, Bytes::from("echo"), timeout).await;
+        assert_eq!(response.expect("request failed"), "echo");
+        let response = request(&server_a, NAME_B, Bytes::from("drop"), timeout).await;
+        assert!(matches!(
+            response,
+            Err(RpcError::Reset(REQUEST_DROPPED_CODE))
+        ));
+        let response = request(&server_a, NAME_B, Bytes::from("hold"), timeout).await;
+        assert!(matches!(response, Err(RpcError::Timeout)));
+        assert_eq!(held.lock().unwrap().len(), 1);
+        let response = request(&server_a, NAME_A, Bytes::from("echo"), timeout).await;
+        assert!(matches!(response, Err(RpcError::UnknownPeer(_))));
+    }
+
+    #[tokio::test]
+    async fn test_request_disconnected() {
+        let server_a = make_server(CONFIG_A);
+        // hold requests without responding
+        let held = Mutex::new(Vec::new());
+        let server_b = make_server_with_handler(
+            CONFIG_B,
+            Arc::new(move |event| {
+                if let ServerEvent::Request { responder, .. } = event {
+                    held.lock().unwrap().push(responder);
+                }
+            }),
+        );
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        let pending = request(&server_a, NAME_B, Bytes::from("hi"), SHUTDOWN_TIMEOUT);
+        let abort = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            server_b.command(ServerCommand::Abort).unwrap();
+        };
+        let (response, _) = tokio::join!(pending, abort);
+        assert!(matches!(response, Err(RpcError::Disconnected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_datagram() {
+        let server_a = make_server(CONFIG_A);
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let server_b = make_server_with_handler(
+            CONFIG_B,
+            Arc::new(move |event| {
+                if let ServerEvent::Datagram { .. } = event {
+                    let _ = events.send(event);
+                }
+            }),
+        );
+        let name_b = dns_name(NAME_B);
+        assert!(matches!(
+            server_a.max_datagram_size(&name_b),
+            Err(DatagramError::UnknownPeer(_))
+        ));
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        server_a
+            .max_datagram_size(&name_b)
+            .expect("datagrams not supported");
+        send_datagram(&server_a, NAME_B, Bytes::from("ping"))
+            .await
+            .expect("failed to send datagram");
+        let Some(ServerEvent::Datagram { peer, payload }) = received.recv().await else {
+            panic!("missing datagram");
+        };
+        assert_eq!(peer, dns_name(NAME_A));
+        assert_eq!(payload, "ping");
+        // larger than any path MTU
+        let result = send_datagram(&server_a, NAME_B, Bytes::from(vec![0; 65536])).await;
+        assert!(matches!(result, Err(DatagramError::TooLarge { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_peer_stats() {
+        let server_a = make_server(CONFIG_A);
+        let server_b = make_server(CONFIG_TRANSPORT);
+        let (name_a, name_b) = (dns_name(NAME_A), dns_name(NAME_B));
+        assert!(server_a.peer_stats(&name_b).is_none());
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        send_datagram(&server_a, NAME_B, Bytes::from("ping"))
+            .await
+            .expect("failed to send");
+        let stats = server_a.peer_stats(&name_b).expect("no stats");
+        assert_eq!(stats.congestion_control, CongestionControl::Cubic);
+        assert!(stats.sent_packets > 0);
+        assert!(stats.cwnd > 0);
+        // the peer registers the connection once the handshake completed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stats = server_b.peer_stats(&name_a).expect("no stats");
+        assert_eq!(stats.congestion_control, CongestionControl::Bbr);
+        assert!(stats.received_bytes > 0);
+        let (reply, stats) = oneshot::channel();
+        server_a
+            .command(ServerCommand::EndpointStats { reply })
+            .unwrap();
+        let stats = stats.await.unwrap();
+        assert_eq!(stats.peers, 1);
+        assert!(stats.sent_bytes > 0 && stats.max_rtt > Duration::ZERO);
+        let (reply, stats) = oneshot::channel();
+        server_a
+            .command(ServerCommand::Stats {
+                peer: dns_name("unknown.peer"),
+                reply,
+            })
+            .unwrap();
+        assert!(stats.await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.metrics = Some(MetricsConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+        });
+        let server_a = Server::init(1, config, Arc::new(|_| {})).expect("failed to init server");
+        let server_b = make_server(CONFIG_B);
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        send(&server_a, NAME_B, Bytes::from("hello"))
+            .await
+            .expect("failed to send");
+        // requests are dropped by the default handler
+        let _ = request(
+            &server_a,
+            NAME_B,
+            Bytes::from("ping"),
+            Duration::from_secs(1),
+        )
+        .await;
+        // B is no longer in the whitelist of A, with TLS 1.3 the client may only
+        // learn about the rejection after its side of the handshake completed
+        let own = vec![NAME_A.parse().unwrap()];
+        update_whitelist(&server_a, WhitelistUpdate::Replace(Some(own)), false).await;
+        let _ = connect(&server_b, &server_a, NAME_A).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let addr = server_a.metrics_addr().expect("metrics not served");
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        for line in [
+            "quicnet_handshakes_succeeded_total{direction=\"outbound\"} 1",
+            "quicnet_handshakes_failed_total{direction=\"inbound\",reason=\"bad_certificate\"} 1",
+            "quicnet_client_certs_rejected_total{reason=\"not_whitelisted\"} 1",
+            "quicnet_active_connections 1",
+            "quicnet_messages_sent_total 1",
+            "quicnet_message_bytes_sent_total 5",
+            "quicnet_requests_total{result=\"reset\"} 1",
+            "quicnet_request_latency_seconds_count 1",
+        ] {
+            assert!(
+                response.lines().any(|l| l == line),
+                "missing {line} in\n{response}"
+            );
+        }
+        assert!(server_a.metrics().contains("quicnet_active_connections 1"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_tls() {
+        let dir = std::env::temp_dir().join("quicnet-reload-tls");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (certs, key) = (dir.join("tls.crt"), dir.join("tls.key"));
+        let install = |name: &str| {
+            std::fs::copy(format!("certs/{name}/{name}.crt"), &certs).unwrap();
+            std::fs::copy(format!("certs/{name}/{name}.key"), &key).unwrap();
+        };
+        install(NAME_B);
+        let mut config = ServerConfig::load(CONFIG_B).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.certs = certs.clone();
+        config.key = key.clone();
+        config.tls_reload_interval_ms = Some(20);
+        let (events, mut errors) = tokio::sync::mpsc::unbounded_channel();
+        let handler = Arc::new(move |event| {
+            if let ServerEvent::Error { error } = event {
+                let _ = events.send(error);
+            }
+        });
+        let server_b = Server::init(1, config, handler).expect("failed to init server");
+        let server_a = make_server(CONFIG_A);
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        assert!(matches!(reload_tls(&server_b).await, Ok(())));
+
+        // a certificate of another domain is rejected
+        install(NAME_A);
+        assert!(matches!(
+            reload_tls(&server_b).await,
+            Err(QuicnetError::CertificateLoad { .. })
+        ));
+        std::fs::write(&key, "invalid").unwrap();
+        assert!(matches!(
+            reload_tls(&server_b).await,
+            Err(QuicnetError::KeyLoad { .. })
+        ));
+        // the modification is picked up by the watcher as well
+        let error = tokio::time::timeout(Duration::from_secs(5), errors.recv())
+            .await
+            .expect("no reload error")
+            .unwrap();
+        assert!(matches!(
+            error,
+            QuicnetError::CertificateLoad { .. } | QuicnetError::KeyLoad { .. }
+        ));
+        // the existing connection and the previous certificate are kept
+        assert!(server_b.peers().contains(&dns_name(NAME_A)));
+        let server_c = make_server_without_whitelist(CONFIG_A);
+        connect(&server_c, &server_b, NAME_B)
+            .await
+            .expect("failed to connect with the previous certificate");
+
+        install(NAME_B);
+        assert!(matches!(reload_tls(&server_b).await, Ok(())));
+    }
+
+    #[tokio::test]
+    async fn test_crl() {
+        let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.whitelist = None;
+        config.crl = Some("certs/crl.pem".into());
+        let server_a = Server::init(1, config, Arc::new(|_| {})).expect("failed to init server");
+        // the certificate of R is revoked by the CRL of A
+        let mut config = ServerConfig::load(CONFIG_B).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.certs = format!("certs/{NAME_REVOKED}/{NAME_REVOKED}.crt").into();
+        config.key = format!("certs/{NAME_REVOKED}/{NAME_REVOKED}.key").into();
+        config.whitelist = None;
+        let server_r = Server::init(1, config, Arc::new(|_| {})).expect("failed to init server");
+        let server_b = make_server_without_whitelist(CONFIG_B);
+
+        // the client may only learn about the rejection after the handshake
+        let _ = connect(&server_r, &server_a, NAME_A).await;
+        assert!(matches!(
+            connect(&server_a, &server_r, NAME_REVOKED).await,
+            Err(QuicnetError::PeerVerification { .. })
+        ));
+        connect(&server_b, &server_a, NAME_A)
+            .await
+            .expect("failed to connect");
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server_a.peers().len(), 1);
+        assert!(server_a.peers().contains(&dns_name(NAME_B)));
+        let metrics = server_a.metrics();
+        for line in [
+            "quicnet_revoked_certificates_total{direction=\"inbound\"} 1",
+            "quicnet_revoked_certificates_total{direction=\"outbound\"} 1",
+            "quicnet_client_certs_rejected_total{reason=\"revoked\"} 1",
+            "quicnet_handshakes_failed_total{direction=\"outbound\",reason=\"revoked\"} 1",
+        ] {
+            assert!(
+                metrics.lines().any(|l| l == line),
+                "missing {line} in\n{metrics}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pins() {
+        let self_signed = format!("certs/{NAME_SELF_SIGNED}/{NAME_SELF_SIGNED}");
+        let certs =
+            load_certificates(format!("{self_signed}.crt"), None).expect("failed to load certs");
+        let pin = SpkiPin::of(&certs[0]).expect("invalid certificate");
+        let make_server_a = || {
+            let mut config = ServerConfig::load(CONFIG_A).expect("failed to load server config");
+            config.addr = "127.0.0.1:0".parse().unwrap();
+            config.whitelist = None;
+            config.peers = Some(vec![
+                PeerConfig {
+                    domain: NAME_SELF_SIGNED.parse().unwrap(),
+                    pins: vec![pin],
+                    pin_only: true,
+                },
+                // B does not carry the pinned key
+                PeerConfig {
+                    domain: NAME_B.parse().unwrap(),
+                    pins: vec![pin],
+                    pin_only: false,
+                },
+            ]);
+            Server::init(1, config, Arc::new(|_| {})).expect("failed to init server")
+        };
+        let make_server_s = || {
+            let mut config = ServerConfig::load(CONFIG_B).expect("failed to load server config");
+            config.addr = "127.0.0.1:0".parse().unwrap();
+            config.certs = format!("{self_signed}.crt").into();
+            config.key = format!("{self_signed}.key").into();
+            Server::init(1, config, Arc::new(|_| {})).expect("failed to init server")
+        };
+        // separate pairs, to avoid duplicate resolution
+        let (server_s, server_s2) = (make_server_s(), make_server_s());
+        let server_a = make_server_a();
+        let server_a2 = make_server_a();
+        let server_b = make_server(CONFIG_B);
+
+        // the self-signed peer is accepted on its pin, in both directions
+        connect(&server_a, &server_s, NAME_SELF_SIGNED)
+            .await
+            .expect("failed to connect");
+        connect(&server_s2, &server_a2, NAME_A)
+            .await
+            .expect("failed to connect");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server_a2.peers().contains(&dns_name(NAME_SELF_SIGNED)));
+        // but not without it
+        let server_c = make_server_without_whitelist(CONFIG_A);
+        assert!(matches!(
+            connect(&server_c, &server_s, NAME_SELF_SIGNED).await,
+            Err(QuicnetError::PeerVerification { .. })
+        ));
+
+        // a trusted certificate without the pinned key is rejected
+        assert!(matches!(
+            connect(&server_a, &server_b, NAME_B).await,
+            Err(QuicnetError::PeerVerification { .. })
+        ));
+        let _ = connect(&server_b, &server_a, NAME_A).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server_a.peers().contains(&dns_name(NAME_B)));
+        assert!(server_a
+            .metrics()
+            .contains("quicnet_client_certs_rejected_total{reason=\"pin_mismatch\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_update_whitelist() {
+        let server_a = make_server(CONFIG_A);
+        let server_b = make_server(CONFIG_B);
+        let name_b = dns_name(NAME_B);
+        // A only permits itself
+        let own = vec![NAME_A.parse().unwrap()];
+        let revoked = update_whitelist(&server_a, WhitelistUpdate::Replace(Some(own)), true);
+        assert!(revoked.await.is_empty());
+        let cn: DomainPattern = ".cn".parse().unwrap();
+        let revoked = update_whitelist(&server_a, WhitelistUpdate::Add(vec![cn.clone()]), true);
+        assert!(revoked.await.is_empty());
+        connect(&server_b, &server_a, NAME_A)
+            .await
+            .expect("failed to connect");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server_a.peers().contains(&name_b));
+
+        let update = WhitelistUpdate::Remove(vec![cn]);
+        assert_eq!(
+            update_whitelist(&server_a, update, true).await,
+            std::slice::from_ref(&name_b)
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server_a.peers().contains(&name_b));
+        assert!(!server_b.peers().contains(&dns_name(NAME_A)));
+        // new connections are rejected, in both directions
+        let _ = connect(&server_b, &server_a, NAME_A).await;
+        assert!(matches!(
+            connect(&server_a, &server_b, NAME_B).await,
+            Err(QuicnetError::PeerVerification { .. })
+        ));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server_a.peers().contains(&name_b));
+
+        let revoked = update_whitelist(&server_a, WhitelistUpdate::Replace(None), true);
+        assert!(revoked.await.is_empty());
+        connect(&server_b, &server_a, NAME_A)
+            .await
+            .expect("failed to connect");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(server_a.peers().contains(&name_b));
+    }
+
+    #[tokio::test]
+    async fn test_peer_events() {
+        let server_a = make_server(CONFIG_A);
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let server_b = make_server_with_handler(
+            CONFIG_B,
+            Arc::new(move |event| match event {
+                ServerEvent::PeerConnected { .. } | ServerEvent::PeerDisconnected { .. } => {
+                    let _ = events.send(event);
+                }
+                _ => {}
+            }),
+        );
+        connect(&server_a, &server_b, NAME_B)
+            .await
+            .expect("failed to connect");
+        let Some(ServerEvent::PeerConnected { peer, direction }) = received.recv().await else {
+            panic!("missing connected event");
+        };
+        assert_eq!(peer, dns_name(NAME_A));
+        assert_eq!(direction, Direction::Inbound);
+        server_a.command(ServerCommand::Abort).unwrap();
+        let Some(ServerEvent::PeerDisconnected { peer, .. }) = received.recv().await else {
+            panic!("missing disconnected event");
+        };
+        assert_eq!(peer, dns_name(NAME_A));
+        assert!(server_b.peers().is_empty());
+    }
+
+    // helper functions
+
+    /// Bind to a random port to avoid conflicting with other tests.
+    fn make_server(config_file: &str) -> Server {
+        let mut config = ServerConfig::load(config_file).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        Server::init(1, config, Arc::new(|_| {})).expect("failed to init server")
+    }
+
+    fn make_server_with_handler(config_file: &str, handler: Arc<dyn EventHandler>) -> Server {
+        let mut config = ServerConfig::load(config_file).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        Server::init(1, config, handler).expect("failed to init server")
+    }
+
+    fn make_server_without_whitelist(config_file: &str) -> Server {
+        let mut config = ServerConfig::load(config_file).expect("failed to load server config");
+        config.addr = "127.0.0.1:0".parse().unwrap();
+        config.whitelist = None;
+        Server::init(1, config, Arc::new(|_| {})).expect("failed to init server")
+    }
+
+    async fn connect(from: &Server, to: &Server, domain: &str) -> Result<(), QuicnetError> {
+        let (reply, result) = oneshot::channel();
+        from.command(ServerCommand::Connect {
+            addr: local_addr(to),
+            domain: dns_name(domain),
+            reply,
+        })
+        .expect("failed to send connect");
+        result.await.expect("connect reply dropped")
+    }
+
+    async fn update_whitelist(
+        server: &Server,
+        update: WhitelistUpdate,
+        close_revoked: bool,
+    ) -> Vec<DnsName> {
+        let (reply, result) = oneshot::channel();
+        server
+            .command(ServerCommand::UpdateWhitelist {
+                update,
+                close_revoked,
+                reply,
+            })
+            .expect("failed to send whitelist update");
+        result.await.expect("whitelist reply dropped")
+    }
+
+    async fn reload_tls(server: &Server) -> Result<(), QuicnetError> {
+        let (reply, result) = oneshot::channel();
+        server
+            .command(ServerCommand::ReloadTls { reply })
+            .expect("failed to send reload");
+        result.await.expect("reload reply dropped")
+    }
+
+    async fn send(from: &Server, peer: &str, payload: Bytes) -> Result<(), SendError> {
+        send_command(from, peer, payload)
+            .await
+            .expect("send reply dropped")
+    }
+
+    /// Start sending, without waiting for the result.
+    fn send_command(
+        from: &Server,
+        peer: &str,
+        payload: Bytes,
+    ) -> oneshot::Receiver<Result<(), SendError>> {
+        let (reply, result) = oneshot::channel();
+        from.command(ServerCommand::Send {
+            peer: dns_name(peer),
+            payload,
+            reply,
+        })
+        .expect("failed to send message");
+        result
+    }
+
+    async fn request(
+        from: &Server,
+        peer: &str,
+        payload: Bytes,
+        timeout: Duration,
+    ) -> Result<Bytes, RpcError> {
+        let (reply, result) = oneshot::channel();
+        from.command(ServerCommand::Request {
+            peer: dns_name(peer),
+            payload,
+            timeout,
+            reply,
+        })
+        .expect("failed to send request");
+        result.await.expect("request reply dropped")
+    }
+
+    async fn send_datagram(from: &Server, peer: &str, payload: Bytes) -> Result<(), DatagramError> {
+        let (reply, result) = oneshot::channel();
+        from.command(ServerCommand::SendDatagram {
+            peer: dns_name(peer),
+            payload,
+            reply,
+        })
+        .expect("failed to send datagram");
+        result.await.expect("datagram reply dropped")
+    }
+
+    /// Raw endpoint with the same crypto config as a server.
+    fn make_endpoint(config_file: &str) -> Endpoint {
+        let config = ServerConfig::load(config_file).expect("failed to load server config");
+        let whitelist = Arc::new(Whitelist::new(config.whitelist.clone()));
+        let (server_config, client_config) = default_config(&config, whitelist, Arc::default())
+            .expect("failed to build server config");
+        let mut endpoint = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap())
+            .expect("init endpoint failed");
+        endpoint.set_default_client_config(client_config);
+        endpoint
+    }
+
+    /// Address of a server bound to a random port.
+    fn local_addr(server: &Server) -> SocketAddr {
+        server.local_addr().expect("failed to get local addr")
+    }
+
+    fn dns_name(name: &str) -> DnsName {
+        DnsName::from(webpki::DnsNameRef::try_from_ascii_str(name).unwrap())
+    }
+}
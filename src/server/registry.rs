@@ -0,0 +1,317 @@
+use super::{
+    event::{EventHandler, ServerEvent},
+    streams::{close_duplicate, Messages},
+};
+use crate::{
+    config::{domain_name::DomainPattern, tls::canonical_dns_name, whitelist::matched_name},
+    error::QuicnetError,
+};
+use dashmap::{mapref::entry::Entry, DashMap};
+use quinn::{Connection, VarInt};
+use rustls::Certificate;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, task::JoinSet};
+use webpki::DnsName;
+
+/// Which side initiated a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Accepted from the peer.
+    Inbound,
+    /// Dialed by this server.
+    Outbound,
+}
+
+/// Live connections keyed by the canonical name of authenticated peers,
+/// see `peer_name`.
+///
+/// Entries are removed automatically once their connection is closed.
+///
+/// Since every node is both server and client, two peers dialing each other
+/// at the same time end up with two connections. Only the connection initiated
+/// by the peer with the smaller canonical name (see `canonical_dns_name`) is
+/// kept, the other is closed with `DUPLICATE_CODE` once the messages written
+/// to it have been delivered.
+/// Both peers apply the same rule, so they agree on the survivor.
+pub struct PeerRegistry {
+    /// Canonical name of this server.
+    local: DnsName,
+    peers: DashMap<DnsName, PeerEntry>,
+    registered: Notify,
+    handler: Arc<dyn EventHandler>,
+}
+
+struct PeerEntry {
+    conn: Connection,
+    direction: Direction,
+    /// Canonical name of the side that dialed the connection, in lowercase.
+    initiator: String,
+    messages: Arc<Messages>,
+}
+
+impl PeerRegistry {
+    pub(crate) fn new(local: DnsName, handler: Arc<dyn EventHandler>) -> Self {
+        Self {
+            local,
+            peers: DashMap::new(),
+            registered: Notify::new(),
+            handler,
+        }
+    }
+
+    /// Register a connection, resolving duplicates with an existing connection
+    /// of the same peer.
+    ///
+    /// Returns the messages of the connection, which must be served
+    /// even if it lost, until the connection is closed.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        peer: DnsName,
+        conn: Connection,
+        direction: Direction,
+    ) -> Arc<Messages> {
+        let messages = Arc::<Messages>::default();
+        let new_entry = PeerEntry {
+            conn: conn.clone(),
+            direction,
+            initiator: self.initiator(&peer, direction),
+            messages: messages.clone(),
+        };
+        let (kept, loser) = match self.peers.entry(peer.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(new_entry);
+                (true, None)
+            }
+            Entry::Occupied(mut entry) => {
+                let old = entry.get();
+                if prefer_new(
+                    (old.direction, &old.initiator),
+                    (direction, &new_entry.initiator),
+                ) {
+                    let old = entry.insert(new_entry);
+                    (true, Some((old.conn, old.messages)))
+                } else {
+                    (false, Some((conn.clone(), messages.clone())))
+                }
+            }
+        };
+        // the dashmap shard is unlocked here, so the handler may use the registry
+        let first = loser.is_none();
+        if let Some((loser, loser_messages)) = loser {
+            tracing::info!(
+                "closing duplicate connection {} to {}",
+                loser.stable_id(),
+                name(&peer)
+            );
+            tokio::spawn(close_duplicate(loser, loser_messages));
+        }
+        if !kept {
+            return messages;
+        }
+        self.registered.notify_waiters();
+        if first {
+            self.handler.on_event(ServerEvent::PeerConnected {
+                peer: peer.clone(),
+                direction,
+            });
+        }
+        let id = conn.stable_id();
+        let registry = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let reason = conn.closed().await;
+            tracing::info!("connection to {} closed: {reason}", name(&peer));
+            if let Some(registry) = registry.upgrade() {
+                // the entry may have been replaced by a newer connection
+                if registry
+                    .peers
+                    .remove_if(&peer, |_, e| e.conn.stable_id() == id)
+                    .is_some()
+                {
+                    registry
+                        .handler
+                        .on_event(ServerEvent::PeerDisconnected { peer, reason });
+                }
+            }
+        });
+        messages
+    }
+
+    /// Name of this server.
+    pub(crate) fn local(&self) -> &DnsName {
+        &self.local
+    }
+
+    /// Look up the connection to `peer`.
+    pub fn get(&self, peer: &DnsName) -> Option<Connection> {
+        self.peers.get(peer).map(|e| e.conn.clone())
+    }
+
+    /// The connection to `peer` and its messages.
+    pub(crate) fn messages(&self, peer: &DnsName) -> Option<(Connection, Arc<Messages>)> {
+        self.peers
+            .get(peer)
+            .map(|e| (e.conn.clone(), e.messages.clone()))
+    }
+
+    /// Which side initiated the connection to `peer`.
+    pub fn direction(&self, peer: &DnsName) -> Option<Direction> {
+        self.peers.get(peer).map(|e| e.direction)
+    }
+
+    /// Whether a connection to `peer` is registered.
+    pub fn contains(&self, peer: &DnsName) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    /// Snapshot of all registered peers and their connections.
+    pub fn iter(&self) -> impl Iterator<Item = (DnsName, Connection)> + '_ {
+        self.peers
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.conn.clone()))
+    }
+
+    /// Finish the message streams of all registered peers before they are
+    /// closed with `code`, see `Messages::finish`.
+    pub(crate) async fn finish_messages(&self, code: VarInt) {
+        let mut finishing = JoinSet::new();
+        for entry in self.peers.iter() {
+            let (conn, messages) = (entry.conn.clone(), entry.messages.clone());
+            finishing.spawn(async move { messages.finish(&conn, code, false).await });
+        }
+        while finishing.join_next().await.is_some() {}
+    }
+
+    /// Number of registered peers.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Wait at most `timeout` for a connection to `peer` other than `conn`.
+    ///
+    /// Sends and requests on a connection closed with `DUPLICATE_CODE`
+    /// are migrated to the connection returned here.
+    pub async fn superseding(
+        &self,
+        peer: &DnsName,
+        conn: &Connection,
+        timeout: Duration,
+    ) -> Option<Connection> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // create the future before checking to not miss a registration
+            let registered = self.registered.notified();
+            match self.get(peer) {
+                Some(next) if next.stable_id() != conn.stable_id() => return Some(next),
+                _ => {}
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            tokio::time::timeout(remaining, registered).await.ok()?;
+        }
+    }
+
+    /// Canonical name of the side that dialed `conn`, in lowercase.
+    fn initiator(&self, peer: &DnsName, direction: Direction) -> String {
+        let initiator = match direction {
+            Direction::Outbound => &self.local,
+            // the peer is registered under its canonical name
+            Direction::Inbound => peer,
+        };
+        name(initiator).to_ascii_lowercase()
+    }
+}
+
+/// The end-entity certificate of an authenticated peer.
+pub(crate) fn peer_certificate(conn: &Connection) -> Option<Certificate> {
+    conn.peer_identity()?
+        .downcast::<Vec<Certificate>>()
+        .ok()?
+        .first()
+        .cloned()
+}
+
+/// Duplicate resolution rule, given the direction and initiator of each connection.
+///
+/// A reconnection in the same direction replaces the stale connection,
+/// otherwise the connection initiated by the smaller canonical name wins.
+fn prefer_new(
+    (old_direction, old_initiator): (Direction, &str),
+    (new_direction, new_initiator): (Direction, &str),
+) -> bool {
+    old_direction == new_direction || new_initiator < old_initiator
+}
+
+/// Resolve the domain name of an authenticated peer.
+///
+/// With a list of candidate patterns, the peer must present a name they
+/// permit, see `matched_name`. The peer is named after the canonical name
+/// of its certificate, see `canonical_dns_name`, so that its inbound and
+/// outbound connections are registered under the same name whichever
+/// of its names was dialed. Certificates with only wildcard names are named
+/// after the permitted name.
+pub(crate) fn peer_name(
+    conn: &Connection,
+    candidates: Option<&[DomainPattern]>,
+) -> Result<DnsName, QuicnetError> {
+    let error = |reason: String| QuicnetError::PeerVerification {
+        addr: conn.remote_address(),
+        peer: None,
+        reason,
+    };
+    let end_entity =
+        peer_certificate(conn).ok_or_else(|| error("peer provided no certificate".to_string()))?;
+    let matched = match candidates {
+        Some(candidates) => Some(
+            matched_name(candidates, &end_entity)
+                .map_err(|e| error(format!("invalid peer certificate: {e:?}")))?
+                .ok_or_else(|| {
+                    error("no permitted domain name found in peer certificate".to_string())
+                })?,
+        ),
+        None => None,
+    };
+    canonical_dns_name(&end_entity)
+        .map_err(|e| error(format!("invalid peer certificate: {e}")))?
+        .or(matched)
+        .ok_or_else(|| error("no domain name found in peer certificate".to_string()))
+}
+
+/// Display helper for peer names.
+#[inline(always)]
+pub(crate) fn name(peer: &DnsName) -> &str {
+    AsRef::<str>::as_ref(peer)
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use super::*;
+
+    #[test]
+    fn test_prefer_new() {
+        use Direction::*;
+        // the canonical names of the servers, as computed on both sides
+        let (small, large) = ("a.example", "b.example");
+        // both sides keep the connection dialed by `small`
+        let on_small = |old, new| {
+            let initiator = |direction| if direction == Outbound { small } else { large };
+            prefer_new((old, initiator(old)), (new, initiator(new)))
+        };
+        let on_large = |old, new| {
+            let initiator = |direction| if direction == Outbound { large } else { small };
+            prefer_new((old, initiator(old)), (new, initiator(new)))
+        };
+        assert!(on_small(Inbound, Outbound));
+        assert!(!on_small(Outbound, Inbound));
+        assert!(on_large(Outbound, Inbound));
+        assert!(!on_large(Inbound, Outbound));
+        // reconnections replace stale connections
+        assert!(on_small(Inbound, Inbound));
+        assert!(on_small(Outbound, Outbound));
+    }
+}
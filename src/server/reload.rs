@@ -0,0 +1,160 @@
+use super::{event::ServerEvent, Server, ServerCommand, ServerState};
+use crate::{
+    config::{crl::crl_files, quic::default_config},
+    error::QuicnetError,
+};
+use quinn::Endpoint;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::{
+    mpsc::{UnboundedSender, WeakUnboundedSender},
+    oneshot, Mutex,
+};
+
+/// New TLS configs, swapped in by the command loop, see `Server::main`.
+pub(super) struct TlsSwap {
+    server_config: quinn::ServerConfig,
+    client_config: quinn::ClientConfig,
+    reply: oneshot::Sender<Result<(), QuicnetError>>,
+}
+
+impl TlsSwap {
+    /// Use the new configs for new connections, existing ones are kept.
+    ///
+    /// `dialer` is the endpoint handle outbound connections are dialed with.
+    pub(super) fn apply(self, endpoint: &Endpoint, dialer: &mut Endpoint) {
+        endpoint.set_server_config(Some(self.server_config));
+        dialer.set_default_client_config(self.client_config);
+        tracing::info!("reloaded TLS certificates");
+        let _ = self.reply.send(Ok(()));
+    }
+}
+
+/// Re-read `ca`, `certs`, `key` and `crl`, and send the new configs
+/// to `swaps`, replying once they are applied.
+///
+/// Runs beside the command loop, which only applies the swap. Reloads
+/// hold `reloading`, so that their swaps are sent in order. Nothing is
+/// changed if the new files are invalid, or if the certificate names a
+/// different domain.
+pub(super) async fn reload_tls(
+    state: Arc<ServerState>,
+    reloading: Arc<Mutex<()>>,
+    swaps: UnboundedSender<TlsSwap>,
+    reply: oneshot::Sender<Result<(), QuicnetError>>,
+) {
+    let _reloading = reloading.lock().await;
+    let loading = state.clone();
+    match blocking(move || load_tls(&loading)).await {
+        Ok((server_config, client_config)) => {
+            // dropped with the reply if the command loop exited
+            let _ = swaps.send(TlsSwap {
+                server_config,
+                client_config,
+                reply,
+            });
+        }
+        Err(e) => {
+            tracing::warn!("failed to reload TLS certificates: {e}");
+            let _ = reply.send(Err(e));
+        }
+    }
+}
+
+/// Read and check the TLS files, blocking.
+fn load_tls(
+    state: &ServerState,
+) -> Result<(quinn::ServerConfig, quinn::ClientConfig), QuicnetError> {
+    let (server_config, client_config) = default_config(
+        &state.config,
+        state.whitelist.clone(),
+        state.metrics.clone(),
+    )?;
+    let local = Server::local_name(&state.config)?;
+    if local != *state.peers.local() {
+        return Err(QuicnetError::CertificateLoad {
+            path: state.config.certs.clone(),
+            reason: format!(
+                "certificate names {} instead of {}",
+                AsRef::<str>::as_ref(&local),
+                AsRef::<str>::as_ref(state.peers.local())
+            ),
+        });
+    }
+    Ok((server_config, client_config))
+}
+
+/// Reload the TLS files whenever one of them is modified, checking every `interval`.
+///
+/// Reloads are sent to the command loop as `ServerCommand::ReloadTls`.
+/// Failed reloads are reported as `ServerEvent::Error`, and retried
+/// only once the files are modified again.
+pub(super) async fn watch_tls(
+    state: Arc<ServerState>,
+    commands: WeakUnboundedSender<ServerCommand>,
+    interval: Duration,
+) {
+    let mut last = modified(&state).await;
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let current = modified(&state).await;
+        if current == last {
+            continue;
+        }
+        last = current;
+        let (reply, result) = oneshot::channel();
+        let sent = commands
+            .upgrade()
+            .is_some_and(|commands| commands.send(ServerCommand::ReloadTls { reply }).is_ok());
+        if !sent {
+            return;
+        }
+        // failures are logged by `reload_tls`
+        if let Ok(Err(e)) = result.await {
+            state.handler.on_event(ServerEvent::Error { error: e });
+        }
+    }
+}
+
+/// Modification times of the TLS files, `None` for unreadable files.
+async fn modified(state: &Arc<ServerState>) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let state = state.clone();
+    blocking(move || modified_files(&state)).await
+}
+
+/// Like `modified`, blocking.
+///
+/// A CRL directory is listed again on every check, to notice added and removed files.
+fn modified_files(state: &ServerState) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let config = &state.config;
+    let mut paths = vec![config.ca.clone(), config.certs.clone(), config.key.clone()];
+    if let Some(crl) = &config.crl {
+        match crl_files(crl) {
+            Ok(files) => paths.extend(files),
+            Err(_) => paths.push(crl.clone()),
+        }
+    }
+    paths
+        .into_iter()
+        .map(|path| {
+            let time = modified_time(&path);
+            (path, time)
+        })
+        .collect()
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Run `f` on a blocking thread, so that file reads do not stall the runtime threads.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
@@ -0,0 +1,240 @@
+use super::{
+    event::ServerEvent,
+    framing::{read_frame, write_frame, write_kind, FrameError, StreamKind},
+    inflight::InflightGuard,
+    registry::name,
+    streams::MIGRATE_TIMEOUT,
+    ServerState, DUPLICATE_CODE, FRAME_TOO_LARGE_CODE, REQUEST_DROPPED_CODE,
+};
+use bytes::Bytes;
+use quinn::{
+    ApplicationClose, Connection, ConnectionError, ReadError, RecvStream, SendStream, VarInt,
+    WriteError,
+};
+use std::{
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{runtime::Handle, sync::oneshot};
+use webpki::DnsName;
+
+/// Failure of a request.
+#[derive(Debug)]
+pub enum RpcError {
+    /// No connection to the peer is registered.
+    UnknownPeer(DnsName),
+    /// No response within the timeout.
+    Timeout,
+    /// The request stream was reset or stopped by the peer with an error code,
+    /// `REQUEST_DROPPED_CODE` if the peer dropped the request without responding.
+    Reset(VarInt),
+    /// The connection to the peer was lost.
+    Disconnected(ConnectionError),
+    Frame(FrameError),
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::UnknownPeer(peer) => write!(f, "not connected to {}", name(peer)),
+            RpcError::Timeout => write!(f, "request timed out"),
+            RpcError::Reset(code) => write!(f, "request reset by peer: error {code}"),
+            RpcError::Disconnected(e) => write!(f, "peer disconnected: {e}"),
+            RpcError::Frame(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<FrameError> for RpcError {
+    fn from(e: FrameError) -> Self {
+        match e {
+            FrameError::Read(ReadError::Reset(code))
+            | FrameError::Write(WriteError::Stopped(code)) => RpcError::Reset(code),
+            FrameError::Read(ReadError::ConnectionLost(e))
+            | FrameError::Write(WriteError::ConnectionLost(e)) => RpcError::Disconnected(e),
+            e => RpcError::Frame(e),
+        }
+    }
+}
+
+/// Responds to a request received from a peer.
+///
+/// Dropping it without responding resets the stream with `REQUEST_DROPPED_CODE`.
+pub struct Responder {
+    send: Option<SendStream>,
+    max_frame_size: usize,
+    runtime: Handle,
+    inflight: Option<InflightGuard>,
+}
+
+impl std::fmt::Debug for Responder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Responder").finish_non_exhaustive()
+    }
+}
+
+impl Responder {
+    /// Send the response, can be called from any thread.
+    ///
+    /// The response is written in the background,
+    /// only an oversized response is reported.
+    pub fn respond(mut self, payload: Bytes) -> Result<(), FrameError> {
+        if payload.len() > self.max_frame_size {
+            // the stream is reset on drop
+            return Err(FrameError::TooLarge {
+                size: payload.len(),
+                max: self.max_frame_size,
+            });
+        }
+        let mut send = self.send.take().expect("responder already used");
+        let max = self.max_frame_size;
+        let inflight = self.inflight.take();
+        self.runtime.spawn(async move {
+            let _inflight = inflight;
+            let result = match write_frame(&mut send, payload, max).await {
+                Ok(()) => send.finish().await.map_err(FrameError::Write),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                tracing::debug!("failed to send response: {e}");
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        if let Some(mut send) = self.send.take() {
+            let _ = send.reset(REQUEST_DROPPED_CODE);
+        }
+    }
+}
+
+/// Send a request to `peer` and reply with the response.
+pub(super) async fn request(
+    state: Arc<ServerState>,
+    peer: DnsName,
+    payload: Bytes,
+    timeout: Duration,
+    reply: oneshot::Sender<Result<Bytes, RpcError>>,
+) {
+    let _inflight = state.inflight.start();
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, try_request(&state, &peer, payload))
+        .await
+        .unwrap_or(Err(RpcError::Timeout));
+    if let Err(e) = &result {
+        tracing::warn!("request to {} failed: {e}", name(&peer));
+    }
+    let label = match &result {
+        Ok(_) => "ok",
+        Err(RpcError::UnknownPeer(_)) => "unknown_peer",
+        Err(RpcError::Timeout) => "timeout",
+        Err(RpcError::Reset(_)) => "reset",
+        Err(RpcError::Disconnected(_)) => "disconnected",
+        Err(RpcError::Frame(_)) => "frame",
+    };
+    state.metrics.request_finished(label, start.elapsed());
+    // the caller may not wait for the result
+    let _ = reply.send(result);
+}
+
+/// Open a fresh stream, write the request, finish, and read the response.
+async fn try_request(
+    state: &ServerState,
+    peer: &DnsName,
+    payload: Bytes,
+) -> Result<Bytes, RpcError> {
+    let max = state.max_frame_size;
+    if payload.len() > max {
+        return Err(RpcError::Frame(FrameError::TooLarge {
+            size: payload.len(),
+            max,
+        }));
+    }
+    let (conn, _open) = start_request(state, peer).await?;
+    let (mut send, mut recv) = conn.open_bi().await.map_err(RpcError::Disconnected)?;
+    write_kind(&mut send, StreamKind::Request).await?;
+    write_frame(&mut send, payload, max).await?;
+    send.finish().await.map_err(FrameError::Write)?;
+    match read_frame(&mut recv, max).await? {
+        Some(response) => Ok(response),
+        None => Err(RpcError::Frame(FrameError::Truncated)),
+    }
+}
+
+/// Pick the connection to send a request to `peer` on, and mark the request
+/// as open on it, see `close_duplicate`.
+///
+/// A connection closing as a duplicate is not used,
+/// the request is migrated to the connection superseding it.
+async fn start_request(
+    state: &ServerState,
+    peer: &DnsName,
+) -> Result<(Connection, InflightGuard), RpcError> {
+    let (conn, messages) = state
+        .peers
+        .messages(peer)
+        .ok_or_else(|| RpcError::UnknownPeer(peer.clone()))?;
+    if let Some(open) = messages.start_request() {
+        return Ok((conn, open));
+    }
+    let superseded = || {
+        RpcError::Disconnected(ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: DUPLICATE_CODE,
+            reason: Bytes::new(),
+        }))
+    };
+    let next = state
+        .peers
+        .superseding(peer, &conn, MIGRATE_TIMEOUT)
+        .await
+        .ok_or_else(superseded)?;
+    tracing::debug!("migrating request to {} to new connection", name(peer));
+    state
+        .peers
+        .messages(peer)
+        .filter(|(c, _)| c.stable_id() == next.stable_id())
+        .and_then(|(c, messages)| Some((c, messages.start_request()?)))
+        .ok_or_else(superseded)
+}
+
+/// Read a request and hand it to the application with a `Responder`.
+pub(super) async fn handle_request(
+    state: &ServerState,
+    peer: &DnsName,
+    mut send: SendStream,
+    mut recv: RecvStream,
+) {
+    let inflight = state.inflight.start();
+    let payload = match read_frame(&mut recv, state.max_frame_size).await {
+        Ok(Some(payload)) => payload,
+        Ok(None) => {
+            tracing::debug!("empty request stream from {}", name(peer));
+            return;
+        }
+        Err(e) => {
+            tracing::warn!("rejected request from {}: {e}", name(peer));
+            if let FrameError::TooLarge { .. } = e {
+                let _ = recv.stop(FRAME_TOO_LARGE_CODE);
+                let _ = send.reset(FRAME_TOO_LARGE_CODE);
+            }
+            return;
+        }
+    };
+    let responder = Responder {
+        send: Some(send),
+        max_frame_size: state.max_frame_size,
+        runtime: Handle::current(),
+        inflight: Some(inflight),
+    };
+    state.handler.on_event(ServerEvent::Request {
+        peer: peer.clone(),
+        payload,
+        responder,
+    });
+}
@@ -0,0 +1,100 @@
+use super::ServerState;
+use crate::config::quic::CongestionControl;
+use quinn::Connection;
+use std::time::Duration;
+use webpki::DnsName;
+
+/// Snapshot of the connection to a peer.
+#[derive(Clone, Debug)]
+pub struct PeerStats {
+    /// Congestion controller of the connection.
+    pub congestion_control: CongestionControl,
+    /// Current round-trip time estimate.
+    pub rtt: Duration,
+    /// Current congestion window in bytes.
+    pub cwnd: u64,
+    /// Congestion events, e.g. losses, since the connection was established.
+    pub congestion_events: u64,
+    /// QUIC packets sent.
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    /// UDP datagrams and bytes sent and received, including QUIC overhead.
+    pub sent_datagrams: u64,
+    pub sent_bytes: u64,
+    pub received_datagrams: u64,
+    pub received_bytes: u64,
+    /// Black holes detected by path MTU discovery.
+    pub black_holes_detected: u64,
+    /// Proxy of the path MTU, which quinn 0.10 does not expose: the largest
+    /// datagram payload fitting it, the MTU less the QUIC packet overhead.
+    /// `None` if datagrams are not supported by both sides.
+    pub path_mtu_proxy: Option<usize>,
+}
+
+/// Aggregate over all connected peers.
+#[derive(Clone, Debug, Default)]
+pub struct EndpointStats {
+    pub peers: usize,
+    /// Largest round-trip time of any peer.
+    pub max_rtt: Duration,
+    pub congestion_events: u64,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    pub sent_datagrams: u64,
+    pub sent_bytes: u64,
+    pub received_datagrams: u64,
+    pub received_bytes: u64,
+}
+
+impl EndpointStats {
+    fn add(&mut self, peer: &PeerStats) {
+        self.peers += 1;
+        self.max_rtt = self.max_rtt.max(peer.rtt);
+        self.congestion_events += peer.congestion_events;
+        self.sent_packets += peer.sent_packets;
+        self.lost_packets += peer.lost_packets;
+        self.lost_bytes += peer.lost_bytes;
+        self.sent_datagrams += peer.sent_datagrams;
+        self.sent_bytes += peer.sent_bytes;
+        self.received_datagrams += peer.received_datagrams;
+        self.received_bytes += peer.received_bytes;
+    }
+}
+
+/// Snapshot of the connection to `peer`, `None` if not connected.
+pub(super) fn peer_stats(state: &ServerState, peer: &DnsName) -> Option<PeerStats> {
+    state
+        .peers
+        .get(peer)
+        .map(|conn| snapshot(state.congestion_control, &conn))
+}
+
+/// Aggregate over all connected peers.
+pub(super) fn endpoint_stats(state: &ServerState) -> EndpointStats {
+    let mut stats = EndpointStats::default();
+    for (_, conn) in state.peers.iter() {
+        stats.add(&snapshot(state.congestion_control, &conn));
+    }
+    stats
+}
+
+fn snapshot(congestion_control: CongestionControl, conn: &Connection) -> PeerStats {
+    let stats = conn.stats();
+    PeerStats {
+        congestion_control,
+        rtt: conn.rtt(),
+        cwnd: stats.path.cwnd,
+        congestion_events: stats.path.congestion_events,
+        sent_packets: stats.path.sent_packets,
+        lost_packets: stats.path.lost_packets,
+        lost_bytes: stats.path.lost_bytes,
+        sent_datagrams: stats.udp_tx.datagrams,
+        sent_bytes: stats.udp_tx.bytes,
+        received_datagrams: stats.udp_rx.datagrams,
+        received_bytes: stats.udp_rx.bytes,
+        black_holes_detected: stats.path.black_holes_detected,
+        path_mtu_proxy: conn.max_datagram_size(),
+    }
+}
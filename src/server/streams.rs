@@ -0,0 +1,401 @@
+use super::{
+    datagram::receive_datagrams,
+    event::ServerEvent,
+    framing::{read_frame, read_kind, write_frame, write_kind, FrameError, StreamKind},
+    inflight::{Inflight, InflightGuard},
+    registry::name,
+    rpc::handle_request,
+    ServerState, DUPLICATE_CODE, FRAME_TOO_LARGE_CODE, UNKNOWN_STREAM_CODE,
+};
+use crate::error::QuicnetError;
+use bytes::Bytes;
+use quinn::{
+    ApplicationClose, Connection, ConnectionError, RecvStream, SendStream, VarInt, WriteError,
+};
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{oneshot, Mutex, Notify};
+use webpki::DnsName;
+
+/// How long a send waits for the connection superseding a duplicate.
+pub(super) const MIGRATE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a duplicate connection is given to deliver the messages
+/// and requests written to it.
+const DUPLICATE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages of a connection, in both directions, and the requests sent on it.
+#[derive(Default)]
+pub(crate) struct Messages {
+    outgoing: Mutex<Outgoing>,
+    /// Set once the peer finished its message stream, see `close_duplicate`.
+    peer_finished: AtomicBool,
+    finished: Notify,
+    requests: std::sync::Mutex<Requests>,
+}
+
+/// Requests sent on a connection and awaiting their response.
+#[derive(Default)]
+struct Requests {
+    open: Arc<Inflight>,
+    /// Set once the connection lost duplicate resolution, no more requests are sent on it.
+    closed: bool,
+}
+
+/// Lazily opened stream reused by all messages sent on a connection.
+///
+/// The peer finishes the receive side once it has read the whole stream.
+#[derive(Default)]
+struct Outgoing {
+    stream: Option<(SendStream, RecvStream)>,
+    /// Set once the stream is finished, the connection is then closed with this code.
+    closing: Option<VarInt>,
+}
+
+impl Messages {
+    /// Stop sending messages on `conn`, which is about to be closed with `code`,
+    /// and wait until the peer has read every message written so far.
+    ///
+    /// With `always`, a stream is opened if none was, so that the peer
+    /// learns that no more messages follow.
+    pub(super) async fn finish(&self, conn: &Connection, code: VarInt, always: bool) {
+        let stream = {
+            let mut outgoing = self.outgoing.lock().await;
+            outgoing.closing = Some(code);
+            outgoing.stream.take()
+        };
+        let (mut send, mut recv) = match stream {
+            Some(stream) => stream,
+            None if always => match open_message_stream(conn).await {
+                Ok(stream) => stream,
+                Err(_) => return,
+            },
+            None => return,
+        };
+        if send.finish().await.is_ok() {
+            let _ = recv.read_to_end(0).await;
+        }
+    }
+
+    /// Mark a request as sent on the connection until the guard is dropped,
+    /// `None` if the connection is closing as a duplicate.
+    pub(super) fn start_request(&self) -> Option<InflightGuard> {
+        let requests = self.requests.lock().unwrap();
+        (!requests.closed).then(|| requests.open.start())
+    }
+
+    /// Stop sending requests and wait until those sent have been answered.
+    async fn finish_requests(&self) {
+        let open = {
+            let mut requests = self.requests.lock().unwrap();
+            requests.closed = true;
+            requests.open.clone()
+        };
+        open.wait_idle().await;
+    }
+
+    /// The peer finished its message stream, every message it sent was delivered.
+    fn set_peer_finished(&self) {
+        self.peer_finished.store(true, Ordering::Release);
+        self.finished.notify_waiters();
+    }
+
+    /// Wait until the peer finished its message stream.
+    async fn wait_peer_finished(&self) {
+        loop {
+            // create the future before checking to not miss a notification
+            let finished = self.finished.notified();
+            if self.peer_finished.load(Ordering::Acquire) {
+                return;
+            }
+            finished.await;
+        }
+    }
+}
+
+/// Close a connection that lost duplicate resolution with `DUPLICATE_CODE`,
+/// once the messages and requests written to it in both directions have been
+/// delivered.
+///
+/// Both peers close the same connection, each waiting for the responses to
+/// its requests, then finishing its message stream and waiting for the peer
+/// to finish its own, so neither closes it while messages or requests are in
+/// flight. Sends and requests started after are migrated to the surviving
+/// connection. Gives up after `DUPLICATE_DRAIN_TIMEOUT`.
+pub(super) async fn close_duplicate(conn: Connection, messages: Arc<Messages>) {
+    let drain = async {
+        // the peer finishes its message stream once its requests were answered
+        messages.finish_requests().await;
+        tokio::join!(
+            messages.finish(&conn, DUPLICATE_CODE, true),
+            messages.wait_peer_finished()
+        )
+    };
+    if tokio::time::timeout(DUPLICATE_DRAIN_TIMEOUT, drain)
+        .await
+        .is_err()
+    {
+        tracing::debug!(
+            "duplicate connection {} not drained in time",
+            conn.stable_id()
+        );
+    }
+    conn.close(DUPLICATE_CODE, b"duplicate connection");
+}
+
+/// Failure of sending a message.
+#[derive(Debug)]
+pub enum SendError {
+    /// No connection to the peer is registered.
+    UnknownPeer(DnsName),
+    /// Failed to open a stream.
+    Connection(ConnectionError),
+    Frame(FrameError),
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::UnknownPeer(peer) => write!(f, "not connected to {}", name(peer)),
+            SendError::Connection(e) => write!(f, "failed to open stream: {e}"),
+            SendError::Frame(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl SendError {
+    /// The connection is being closed with `code`, no more messages are sent on it.
+    fn closing(code: VarInt) -> Self {
+        SendError::Connection(ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: code,
+            reason: Bytes::new(),
+        }))
+    }
+
+    /// Whether the connection was closed by duplicate resolution,
+    /// in which case the send is migrated to the surviving connection.
+    fn is_superseded(&self) -> bool {
+        let e = match self {
+            SendError::Connection(e) => e,
+            SendError::Frame(FrameError::Write(WriteError::ConnectionLost(e))) => e,
+            _ => return false,
+        };
+        // connections closed locally, e.g. on abort, are not superseded
+        matches!(e, ConnectionError::ApplicationClosed(close) if close.error_code == DUPLICATE_CODE)
+    }
+}
+
+/// Send `payload` as a frame on the message stream to `peer`, and reply the result.
+pub(super) async fn send_message(
+    state: Arc<ServerState>,
+    peer: DnsName,
+    payload: Bytes,
+    reply: oneshot::Sender<Result<(), SendError>>,
+) {
+    let _inflight = state.inflight.start();
+    let len = payload.len();
+    let result = try_send_message(&state, &peer, payload).await;
+    match &result {
+        Ok(()) => state.metrics.message_sent(len),
+        Err(e) => tracing::warn!("failed to send message to {}: {e}", name(&peer)),
+    }
+    // the caller may not wait for the result
+    let _ = reply.send(result);
+}
+
+async fn try_send_message(
+    state: &ServerState,
+    peer: &DnsName,
+    payload: Bytes,
+) -> Result<(), SendError> {
+    let max = state.max_frame_size;
+    if payload.len() > max {
+        return Err(SendError::Frame(FrameError::TooLarge {
+            size: payload.len(),
+            max,
+        }));
+    }
+    let (conn, messages) = state
+        .peers
+        .messages(peer)
+        .ok_or_else(|| SendError::UnknownPeer(peer.clone()))?;
+    match write_message(&conn, &messages, payload.clone(), max).await {
+        Err(e) if e.is_superseded() => {
+            let Some(next) = state.peers.superseding(peer, &conn, MIGRATE_TIMEOUT).await else {
+                return Err(e);
+            };
+            tracing::debug!("migrating message to {} to new connection", name(peer));
+            let (_, messages) = state
+                .peers
+                .messages(peer)
+                .filter(|(c, _)| c.stable_id() == next.stable_id())
+                .ok_or(e)?;
+            write_message(&next, &messages, payload, max).await
+        }
+        result => result,
+    }
+}
+
+/// Write a frame to the message stream, opening it if needed.
+///
+/// The stream is dropped on failure, so that the next message opens a new one.
+async fn write_message(
+    conn: &Connection,
+    messages: &Messages,
+    payload: Bytes,
+    max: usize,
+) -> Result<(), SendError> {
+    let mut outgoing = messages.outgoing.lock().await;
+    if let Some(code) = outgoing.closing {
+        return Err(SendError::closing(code));
+    }
+    let (send, _) = match outgoing.stream.as_mut() {
+        Some(stream) => stream,
+        None => outgoing.stream.insert(open_message_stream(conn).await?),
+    };
+    let result = write_frame(send, payload, max)
+        .await
+        .map_err(SendError::Frame);
+    if result.is_err() {
+        outgoing.stream = None;
+    }
+    result
+}
+
+async fn open_message_stream(conn: &Connection) -> Result<(SendStream, RecvStream), SendError> {
+    let (mut send, recv) = conn.open_bi().await.map_err(SendError::Connection)?;
+    write_kind(&mut send, StreamKind::Message)
+        .await
+        .map_err(SendError::Frame)?;
+    Ok((send, recv))
+}
+
+/// Accept streams and datagrams from `peer` until the connection is closed.
+pub(super) async fn serve(
+    state: Arc<ServerState>,
+    peer: DnsName,
+    conn: Connection,
+    messages: Arc<Messages>,
+) {
+    tokio::join!(
+        accept_streams(&state, &peer, &conn, &messages),
+        receive_datagrams(&state, &peer, &conn)
+    );
+}
+
+async fn accept_streams(
+    state: &Arc<ServerState>,
+    peer: &DnsName,
+    conn: &Connection,
+    messages: &Arc<Messages>,
+) {
+    loop {
+        match conn.accept_bi().await {
+            Ok((send, recv)) => {
+                tokio::spawn(handle_stream(
+                    state.clone(),
+                    peer.clone(),
+                    messages.clone(),
+                    send,
+                    recv,
+                ));
+            }
+            Err(e) => {
+                tracing::debug!("stopped accepting streams from {}: {e}", name(peer));
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_stream(
+    state: Arc<ServerState>,
+    peer: DnsName,
+    messages: Arc<Messages>,
+    send: SendStream,
+    mut recv: RecvStream,
+) {
+    match read_kind(&mut recv).await {
+        Ok(Ok(StreamKind::Message)) => receive_messages(&state, &peer, &messages, send, recv).await,
+        Ok(Ok(StreamKind::Request)) => handle_request(&state, &peer, send, recv).await,
+        Ok(Err(kind)) => {
+            tracing::warn!("unknown stream kind {kind} from {}", name(&peer));
+            let _ = recv.stop(UNKNOWN_STREAM_CODE);
+        }
+        Err(e) => tracing::debug!("failed to read stream kind from {}: {e}", name(&peer)),
+    }
+}
+
+/// Deliver messages to the application until the stream finishes.
+///
+/// The send side is finished once every message has been delivered,
+/// and reset if the stream failed.
+async fn receive_messages(
+    state: &ServerState,
+    peer: &DnsName,
+    messages: &Messages,
+    mut send: SendStream,
+    mut recv: RecvStream,
+) {
+    loop {
+        match read_frame(&mut recv, state.max_frame_size).await {
+            Ok(Some(payload)) => {
+                state.metrics.message_received(payload.len());
+                state.handler.on_event(ServerEvent::Message {
+                    peer: peer.clone(),
+                    payload,
+                })
+            }
+            Ok(None) => {
+                messages.set_peer_finished();
+                let _ = send.finish().await;
+                return;
+            }
+            Err(e @ FrameError::TooLarge { .. }) => {
+                tracing::warn!("rejected message from {}: {e}", name(peer));
+                let _ = recv.stop(FRAME_TOO_LARGE_CODE);
+                let _ = send.reset(FRAME_TOO_LARGE_CODE);
+                state.handler.on_event(ServerEvent::Error {
+                    error: QuicnetError::FrameTooLarge {
+                        peer: peer.clone(),
+                        source: e,
+                    },
+                });
+                return;
+            }
+            Err(e) => {
+                tracing::debug!("message stream from {} failed: {e}", name(peer));
+                // not finished, the messages may not all have been delivered
+                let _ = send.reset(VarInt::from_u32(0));
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod streams_tests {
+    use super::*;
+    use crate::server::SHUTDOWN_CODE;
+
+    #[test]
+    fn test_is_superseded() {
+        assert!(SendError::closing(DUPLICATE_CODE).is_superseded());
+        let lost = |e| SendError::Frame(FrameError::Write(WriteError::ConnectionLost(e)));
+        assert!(lost(ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: DUPLICATE_CODE,
+            reason: Bytes::from_static(b"duplicate connection"),
+        }))
+        .is_superseded());
+        assert!(!SendError::closing(SHUTDOWN_CODE).is_superseded());
+        assert!(!SendError::Connection(ConnectionError::LocallyClosed).is_superseded());
+        assert!(!lost(ConnectionError::LocallyClosed).is_superseded());
+    }
+}
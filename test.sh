@@ -1,5 +1,5 @@
 
-./gen-certs.sh ddpwuxrmp.uk rehdhssj.cn
+./gen-certs.sh ddpwuxrmp.uk rehdhssj.cn zqxbnvtk.uk
 
 # create empty certs
 mkdir -p certs/empty/
@@ -10,7 +10,69 @@ touch certs/empty/empty.key
 cat ./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.crt > ./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.pem
 cat ./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.key >>  ./certs/ddpwuxrmp.uk/ddpwuxrmp.uk.pem
 
+# create DER, encrypted and PKCS#12 variants
+cd certs/ddpwuxrmp.uk
+openssl x509 -in ddpwuxrmp.uk.crt -outform der -out ddpwuxrmp.uk.der
+openssl pkey -in ddpwuxrmp.uk.key -outform der -out ddpwuxrmp.uk.key.der
+openssl pkey -in ddpwuxrmp.uk.key -aes256 -passout pass:quicnet -out ddpwuxrmp.uk.encrypted.key
+openssl pkcs12 -export -in ddpwuxrmp.uk.crt -inkey ddpwuxrmp.uk.key -certfile ../RootCA.pem \
+  -out ddpwuxrmp.uk.p12 -passout pass:quicnet -keypbe NONE -certpbe NONE
+openssl pkcs12 -export -in ddpwuxrmp.uk.crt -inkey ddpwuxrmp.uk.key \
+  -out ddpwuxrmp.uk.sha1.p12 -passout pass:quicnet -keypbe NONE -certpbe NONE -macalg sha1
+openssl pkcs12 -export -in ddpwuxrmp.uk.crt -inkey ddpwuxrmp.uk.key \
+  -out ddpwuxrmp.uk.encrypted.p12 -passout pass:quicnet
+openssl pkcs12 -export -in ddpwuxrmp.uk.crt -inkey ddpwuxrmp.uk.key \
+  -out ddpwuxrmp.uk.legacy.p12 -passout pass:quicnet -legacy
+openssl pkcs8 -topk8 -in ddpwuxrmp.uk.key -v2 aes-128-cbc -passout pass:quicnet \
+  -outform der -out ddpwuxrmp.uk.encrypted.key.der
+# legacy encrypted keys, with 3DES, PBES2 with 3DES, and PBES2 with HMAC-SHA-1
+openssl pkcs8 -topk8 -in ddpwuxrmp.uk.key -v1 PBE-SHA1-3DES -passout pass:quicnet \
+  -outform der -out ddpwuxrmp.uk.legacy.key.der
+openssl pkcs8 -topk8 -in ddpwuxrmp.uk.key -v2 des3 -passout pass:quicnet \
+  -outform der -out ddpwuxrmp.uk.des3.key.der
+openssl pkcs8 -topk8 -in ddpwuxrmp.uk.key -v2 aes-128-cbc -v2prf hmacWithSHA1 -passout pass:quicnet \
+  -outform der -out ddpwuxrmp.uk.sha1.key.der
+echo quicnet > password.txt
+cd ../..
+
+# create a CRL revoking the certificate of zqxbnvtk.uk
+cd certs
+touch index.txt
+echo 1000 > crlnumber
+cat > ca.cnf <<EOF
+[ca]
+default_ca = ca_default
+[ca_default]
+database = index.txt
+crlnumber = crlnumber
+default_md = sha256
+default_crl_days = 1024
+EOF
+openssl ca -config ca.cnf -keyfile RootCA.key -cert RootCA.pem -revoke zqxbnvtk.uk/zqxbnvtk.uk.crt
+openssl ca -config ca.cnf -keyfile RootCA.key -cert RootCA.pem -gencrl -out crl.pem
+openssl crl -in crl.pem -outform der -out crl.der
+mkdir crl.d
+cp crl.pem crl.d/
+echo "CRLs of the root CA" > crl.d/README
+
+# create a self-signed certificate for kmvrtxqe.uk, trusted by pin only
+mkdir kmvrtxqe.uk
+openssl req -x509 -nodes -new -sha256 -days 1024 -newkey rsa:2048 \
+  -keyout kmvrtxqe.uk/kmvrtxqe.uk.key -out kmvrtxqe.uk/kmvrtxqe.uk.crt -subj "/C=US/CN=kmvrtxqe.uk" \
+  -addext "subjectAltName=DNS:kmvrtxqe.uk" -addext "basicConstraints=critical,CA:FALSE" \
+  -addext "keyUsage=digitalSignature,keyEncipherment"
+
+# create a certificate with two names, the canonical one listed last
+mkdir wmqxvtr.uk
+openssl req -new -nodes -newkey rsa:2048 -keyout wmqxvtr.uk/wmqxvtr.uk.key -out wmqxvtr.uk/wmqxvtr.uk.csr \
+  -subj "/C=US/CN=wmqxvtr.uk"
+printf "basicConstraints=CA:FALSE\nsubjectAltName=DNS:wmqxvtr.uk,DNS:bqfztnk.uk\n" > wmqxvtr.uk/domains.txt
+openssl x509 -req -sha256 -days 1024 -in wmqxvtr.uk/wmqxvtr.uk.csr -CA RootCA.pem -CAkey RootCA.key \
+  -CAcreateserial -extfile wmqxvtr.uk/domains.txt -out wmqxvtr.uk/wmqxvtr.uk.crt
+cd ..
+
 # rust tests
 cargo test -- --nocapture
+cargo test --features legacy-pkcs12 -- --nocapture
 
 rm -rf certs